@@ -0,0 +1,17 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    // The commit is only known when building from a git checkout
+    let commit = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown commit".to_string());
+
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=TARGET={}", env::var("TARGET").unwrap());
+}
@@ -0,0 +1,166 @@
+use std::io;
+use std::process;
+
+use futures::Future;
+use futures::IntoFuture;
+
+use crate::octoprint::OctoprintClient;
+use crate::octoprint::OctoprintError;
+use crate::octoprint::Origin;
+use crate::octoprint::PrinterAxis;
+use crate::ui::UiError;
+use crate::ui::UiEvent;
+
+/// An action to send to OctoPrint
+#[derive(Debug, Clone)]
+pub enum Command {
+    PauseJob,
+    ResumeJob,
+    CancelJob,
+    LoadFiles,
+    LoadSdFiles,
+    LoadBedMesh,
+    /// Loads the slicer settings and analysis of the selected file
+    LoadFileDetail {
+        origin: Origin,
+        path: String,
+    },
+    /// Opens the webcam stream from the settings in the browser
+    OpenWebcam,
+    /// Loads the job and printer state right away instead of waiting for the next poll
+    Refresh,
+    SelectFile {
+        origin: Origin,
+        path: String,
+    },
+    SetToolTemperature {
+        tool: usize,
+        target: f64,
+    },
+    SetBedTemperature {
+        target: f64,
+    },
+    Jog {
+        x: f64,
+        y: f64,
+        z: f64,
+    },
+    Home {
+        axes: Vec<PrinterAxis>,
+    },
+    /// Connects with the given port and baudrate, or automatically for none
+    Connect {
+        port: Option<String>,
+        baudrate: Option<u32>,
+    },
+    Disconnect,
+    SendGcode {
+        commands: Vec<String>,
+    },
+    PsuOn,
+    PsuOff,
+    /// Switches to another printer profile from the config
+    SwitchProfile {
+        index: usize,
+    },
+}
+
+impl Command {
+    /// Sends the command, resolving to events for the ui with anything the command loaded
+    pub fn send(
+        self,
+        octoprint: &mut OctoprintClient,
+    ) -> Box<dyn Future<Item = Vec<UiEvent>, Error = OctoprintError> + Send> {
+        match self {
+            Command::PauseJob => Box::new(octoprint.pause_job().map(|_| vec![])),
+            Command::ResumeJob => Box::new(octoprint.resume_job().map(|_| vec![])),
+            Command::CancelJob => Box::new(octoprint.cancel_job().map(|_| vec![])),
+            Command::LoadFiles => Box::new(
+                octoprint
+                    .load_files()
+                    .map(|files| vec![UiEvent::FilesUpdate(files)]),
+            ),
+            Command::LoadSdFiles => Box::new(
+                octoprint
+                    .load_sd_files()
+                    .map(|files| vec![UiEvent::SdFilesUpdate(files)]),
+            ),
+            Command::LoadBedMesh => Box::new(
+                octoprint
+                    .load_settings()
+                    .map(|settings| vec![UiEvent::BedMeshUpdate(settings.bed_mesh())]),
+            ),
+            Command::LoadFileDetail { origin, path } => Box::new(
+                octoprint
+                    .load_file(&origin, &path)
+                    .map(|file| vec![UiEvent::FileDetailUpdate(file)]),
+            ),
+            Command::Refresh => {
+                let mut state_client = octoprint.clone();
+                Box::new(octoprint.load_job().and_then(move |job| {
+                    state_client
+                        .load_state()
+                        .map(|state| vec![UiEvent::JobUpdate(job), UiEvent::StateUpdate(state)])
+                }))
+            }
+            Command::OpenWebcam => {
+                let client = octoprint.clone();
+                Box::new(octoprint.load_settings().map(move |settings| {
+                    let error = match settings.webcam_stream_url() {
+                        Some(url) => match client.absolute_url(url) {
+                            Ok(url) => match open_in_browser(&url) {
+                                Ok(()) => return vec![],
+                                Err(e) => format!("Could not open {}: {}", url, e),
+                            },
+                            Err(e) => e.to_string(),
+                        },
+                        None => "No webcam is set up in OctoPrint".to_string(),
+                    };
+                    vec![UiEvent::Error(UiError::Webcam(error))]
+                }))
+            }
+            Command::SelectFile { origin, path } => {
+                Box::new(octoprint.select_file(&origin, &path).map(|_| vec![]))
+            }
+            Command::SetToolTemperature { tool, target } => {
+                Box::new(octoprint.set_tool_temperature(tool, target).map(|_| vec![]))
+            }
+            Command::SetBedTemperature { target } => {
+                Box::new(octoprint.set_bed_temperature(target).map(|_| vec![]))
+            }
+            Command::Jog { x, y, z } => Box::new(octoprint.jog(x, y, z).map(|_| vec![])),
+            Command::Home { axes } => Box::new(octoprint.home(axes).map(|_| vec![])),
+            Command::Connect { port, baudrate } => {
+                Box::new(octoprint.connect(port, baudrate).map(|_| vec![]))
+            }
+            Command::Disconnect => Box::new(octoprint.disconnect().map(|_| vec![])),
+            Command::SendGcode { commands } => {
+                Box::new(octoprint.send_gcode(commands).map(|_| vec![]))
+            }
+            Command::PsuOn => Box::new(octoprint.psu_on().map(|_| vec![])),
+            Command::PsuOff => Box::new(octoprint.psu_off().map(|_| vec![])),
+            // Switching changes the client itself, so it is done where the clients are made
+            Command::SwitchProfile { .. } => Box::new(Ok(vec![]).into_future()),
+        }
+    }
+}
+
+/// Opens a URL with the desktop's default browser, without waiting for it
+fn open_in_browser(url: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = process::Command::new("cmd");
+        command.args(&["/C", "start", ""]);
+        command
+    } else {
+        process::Command::new("xdg-open")
+    };
+    command
+        .arg(url)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
@@ -0,0 +1,237 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::octoprint::AuthScheme;
+use crate::ui::TemperatureUnit;
+use crate::ui::View;
+
+/// A named printer with its own server
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    pub auth: Option<AuthScheme>,
+}
+
+/// Target temperatures to heat to together, in °C
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub tool: f64,
+    pub bed: f64,
+}
+
+impl Preset {
+    /// The presets used when the config file doesn't have any
+    pub fn defaults() -> Vec<Preset> {
+        vec![
+            Preset {
+                name: "PLA".to_string(),
+                tool: 200.0,
+                bed: 60.0,
+            },
+            Preset {
+                name: "PETG".to_string(),
+                tool: 240.0,
+                bed: 80.0,
+            },
+            Preset {
+                name: "Cooldown".to_string(),
+                tool: 0.0,
+                bed: 0.0,
+            },
+        ]
+    }
+}
+
+/// Colors that replace the ones from the theme, as names like `lightred` or hex like `#268bd2`
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ThemeColors {
+    pub foreground: Option<String>,
+    pub background: Option<String>,
+    pub status: Option<String>,
+    pub error: Option<String>,
+    pub gauge: Option<String>,
+    pub heating: Option<String>,
+    pub near_target: Option<String>,
+    pub at_target: Option<String>,
+    pub above_target: Option<String>,
+}
+
+/// Either `theme = "solarized"`, or a `[theme]` table with a name and a `[theme.custom]` table
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ThemeConfig {
+    Name(String),
+    Table {
+        name: Option<String>,
+        #[serde(default)]
+        custom: ThemeColors,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Config {
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    pub auth: Option<AuthScheme>,
+    /// Seconds to wait for a response
+    pub timeout: Option<u64>,
+    /// The biggest response read from the server, in MB
+    pub max_response_mb: Option<usize>,
+    pub jog_step: Option<f64>,
+    /// How far each babystep moves Z, in mm
+    pub babystep: Option<f64>,
+    pub units: Option<TemperatureUnit>,
+    pub notify_command: Option<String>,
+    /// Shell commands to run when a print pauses, resumes, errors or completes
+    pub on_pause: Option<String>,
+    pub on_resume: Option<String>,
+    pub on_error: Option<String>,
+    pub on_complete: Option<String>,
+    pub spool_length: Option<f64>,
+    pub log_file: Option<PathBuf>,
+    /// The name of the profile to start with, until another is switched to in the ui
+    pub profile: Option<String>,
+    /// Set to false to hide the bed temperature, the filament line, the estimated time or the
+    /// fan, speed and flow line. That line only shows while push updates are connected
+    pub show_bed: Option<bool>,
+    pub show_filament: Option<bool>,
+    pub show_estimated: Option<bool>,
+    pub show_tuning: Option<bool>,
+    /// Seconds without a key press or a print before the ui dims
+    pub idle_dim_secs: Option<u64>,
+    /// Digits after the decimal point in temperature readings, 1 by default
+    pub temperature_precision: Option<usize>,
+    /// Written instead of the decimal point in temperature readings, like `,`
+    pub decimal_separator: Option<char>,
+    // Can be a table, so it goes after the plain values
+    pub theme: Option<ThemeConfig>,
+    // Lists of tables must stay the last fields, since toml writes tables after plain values
+    #[serde(default)]
+    pub presets: Vec<Preset>,
+    #[serde(default)]
+    pub profiles: Vec<Profile>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    StateParse(PathBuf, toml::de::Error),
+    StateWrite(PathBuf, io::Error),
+    Serialize(toml::ser::Error),
+    NoStateDir,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => {
+                write!(f, "Could not read config file {}: {}", path.display(), err)
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "Could not parse config file {}: {}", path.display(), err)
+            }
+            ConfigError::StateParse(path, err) => {
+                write!(f, "Could not parse state file {}: {}", path.display(), err)
+            }
+            ConfigError::StateWrite(path, err) => {
+                write!(f, "Could not write state file {}: {}", path.display(), err)
+            }
+            ConfigError::Serialize(err) => write!(f, "Could not save state: {}", err),
+            ConfigError::NoStateDir => write!(f, "Could not find the state directory"),
+        }
+    }
+}
+
+impl Config {
+    /// The config file location, `~/.config/octoprint-tui/config.toml` on Linux
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("octoprint-tui").join("config.toml"))
+    }
+
+    /// The default log file location, `~/.config/octoprint-tui/octoprint-tui.log` on Linux
+    pub fn log_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("octoprint-tui").join("octoprint-tui.log"))
+    }
+
+    /// Loads the config file, or the default config if there is no config file
+    pub fn load() -> Result<Config, ConfigError> {
+        let path = match Config::path() {
+            Some(path) => path,
+            None => return Ok(Config::default()),
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(ConfigError::Io(path, err)),
+        };
+
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path, err))
+    }
+
+    /// Finds a profile by name
+    pub fn find_profile(&self, name: &str) -> Option<usize> {
+        self.profiles
+            .iter()
+            .position(|profile| profile.name == name)
+    }
+}
+
+/// What the ui remembers between runs, kept out of the config file since it changes on its own
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct SavedState {
+    /// The view that was open when the ui last quit
+    pub view: Option<View>,
+    /// The profile in use when the ui last quit, which takes over from the config file's
+    /// `profile` so switching doesn't rewrite the config file
+    pub profile: Option<String>,
+}
+
+impl SavedState {
+    /// The state file location, `~/.local/state/octoprint-tui/state.toml` unless $XDG_STATE_HOME
+    /// is set
+    pub fn path() -> Option<PathBuf> {
+        env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| dirs::home_dir().map(|home| home.join(".local").join("state")))
+            .map(|dir| dir.join("octoprint-tui").join("state.toml"))
+    }
+
+    /// Loads the state file, or the default state if it is missing or can't be read. This runs
+    /// before the log file is opened, so a broken file is left for the caller to log.
+    pub fn load() -> Result<SavedState, ConfigError> {
+        let path = match SavedState::path() {
+            Some(path) => path,
+            None => return Ok(SavedState::default()),
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|err| ConfigError::StateParse(path, err))
+            }
+            Err(_) => Ok(SavedState::default()),
+        }
+    }
+
+    /// Writes the state file
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = SavedState::path().ok_or(ConfigError::NoStateDir)?;
+        let contents = toml::to_string(self).map_err(ConfigError::Serialize)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|err| ConfigError::StateWrite(dir.to_path_buf(), err))?;
+        }
+        fs::write(&path, contents).map_err(|err| ConfigError::StateWrite(path, err))
+    }
+}
@@ -1,86 +1,1027 @@
+mod command;
+mod config;
+mod metrics;
 mod octoprint;
+mod push;
+mod theme;
 mod ui;
+mod webcam;
 
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
 use std::io;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use futures::future::lazy;
-use futures::stream::iter;
+use futures::future::Either;
+use futures::stream;
 use futures::sync::mpsc;
 use futures::sync::oneshot;
 use futures::Future;
+use futures::IntoFuture;
 use futures::Sink;
 use futures::Stream;
+use hyper::StatusCode;
 use tokio::runtime::Runtime;
+use tokio_timer::Delay;
 use tokio_timer::Interval;
 
 use tui::backend::Backend;
 use tui::backend::TermionBackend;
 use tui::Terminal;
 
-use termion::event::Key;
 use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 
+use serde::Serialize;
+use structopt::StructOpt;
+
+use log::error;
+use log::info;
+use log::warn;
+use simplelog::Config as LogConfig;
+use simplelog::LevelFilter;
+use simplelog::WriteLogger;
+
+use command::Command;
+use config::Config;
+use config::Preset;
+use config::SavedState;
+use metrics::SharedMetrics;
 use octoprint::*;
+use theme::Theme;
 use ui::*;
+#[cfg(feature = "webcam")]
+use webcam::Snapshot;
+
+/// The version, commit and target that --version prints for bug reports
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GIT_COMMIT"),
+    ", ",
+    env!("TARGET"),
+    ")"
+);
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "octoprint-tui", raw(version = "VERSION"))]
+struct Opt {
+    /// Base URL of the OctoPrint server, or unix:///path/to/octoprint.sock [default: http://localhost:5000]
+    #[structopt(long = "url")]
+    url: Option<String>,
+
+    /// Name of the printer profile in the config file to use [default: the last used]
+    #[structopt(long = "profile")]
+    profile: Option<String>,
+
+    /// OctoPrint API key
+    #[structopt(long = "api-key")]
+    api_key: Option<String>,
+
+    /// How to send the API key, as an X-Api-Key header or a bearer token [default: api-key]
+    #[structopt(long = "auth")]
+    auth: Option<AuthScheme>,
+
+    /// Seconds to wait for a response from OctoPrint [default: 10]
+    #[structopt(long = "timeout")]
+    timeout: Option<u64>,
+
+    /// Distance to move the print head per key press in jog mode, in mm [default: 10]
+    #[structopt(long = "jog-step")]
+    jog_step: Option<f64>,
+
+    /// Distance to move Z per babystep key press, in mm [default: 0.05]
+    #[structopt(long = "babystep")]
+    babystep: Option<f64>,
+
+    /// Units to show temperatures in, celsius or fahrenheit [default: celsius]
+    #[structopt(long = "units")]
+    units: Option<TemperatureUnit>,
+
+    /// Shell command to run when a print completes, with the file name in $OCTOPRINT_FILE
+    #[structopt(long = "notify-command")]
+    notify_command: Option<String>,
+
+    /// Filament on the spool when the print started, in m, to warn before it runs out
+    #[structopt(long = "spool-length")]
+    spool_length: Option<f64>,
+
+    /// Show every hotend, even extra ones that are off and reading 0
+    #[structopt(long = "show-inactive-tools")]
+    show_inactive_tools: bool,
+
+    /// File to write the log to [default: ~/.config/octoprint-tui/octoprint-tui.log]
+    #[structopt(long = "log-file", parse(from_os_str))]
+    log_file: Option<PathBuf>,
+
+    /// Poll the api instead of using OctoPrint's push socket
+    #[structopt(long = "no-push")]
+    no_push: bool,
+
+    /// Seconds between updates while printing
+    #[structopt(
+        long = "poll-interval",
+        default_value = "1",
+        parse(try_from_str = "parse_poll_interval")
+    )]
+    poll_interval: u64,
+
+    /// Print a one line summary of the print and exit, for status bars like tmux's
+    #[structopt(long = "status-line")]
+    status_line: bool,
+
+    /// Print the job and printer state as one json object and exit
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Check that the server and API key work, print the printer state and exit
+    #[structopt(long = "check")]
+    check: bool,
+
+    /// Print the webcam stream URL and exit
+    #[structopt(long = "webcam-url")]
+    webcam_url: bool,
+
+    /// Load webcam snapshots every few seconds for the preview, needs the webcam feature
+    #[structopt(long = "webcam")]
+    webcam: bool,
+
+    /// Serve the temperatures and progress for Prometheus at http://<address>/metrics
+    #[structopt(long = "metrics-addr")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Draw the overview in a few lines, which short terminals do anyway
+    #[structopt(long = "compact")]
+    compact: bool,
+
+    /// Draw without colors or bold text, also set by the NO_COLOR environment variable
+    #[structopt(long = "no-color")]
+    no_color: bool,
+}
+
+/// Parses --poll-interval, which has to be at least a second so the pollers don't spin
+fn parse_poll_interval(seconds: &str) -> Result<u64, String> {
+    match seconds.parse::<u64>() {
+        Ok(0) => Err("the poll interval has to be at least 1 second".to_string()),
+        Ok(seconds) => Ok(seconds),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// What --json prints, with the fields named as OctoPrint names them
+#[derive(Serialize, Debug)]
+struct JsonStatus {
+    job: JobResponse,
+    state: StateResponse,
+}
+
+/// The server used when none is given
+const DEFAULT_URL: &str = "http://localhost:5000";
+
+/// Seconds to wait for a response when none is given
+const DEFAULT_TIMEOUT: u64 = 10;
+
+/// The biggest response read from the server when no limit is given, in MB
+const DEFAULT_MAX_RESPONSE_MB: usize = 8;
+
+/// Seconds between updates while the printer is not printing
+const IDLE_POLL_INTERVAL: u64 = 5;
+
+/// The wait after the first failed request
+const BACKOFF_START: Duration = Duration::from_secs(1);
+
+/// The longest wait between requests while they keep failing
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// How many updates can be missed before the watchdog reports that updates have stalled
+const WATCHDOG_MISSED_UPDATES: u32 = 10;
+
+/// How often the watchdog checks for updates
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the print history is loaded, since it only changes when a print ends
+const HISTORY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// How often a new webcam snapshot is loaded for the preview
+#[cfg(feature = "webcam")]
+const WEBCAM_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the terminal size is checked for changes
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Polls `load` every `interval` while printing, and every `IDLE_POLL_INTERVAL` otherwise
+///
+/// While requests fail the wait doubles from `BACKOFF_START` up to `BACKOFF_MAX`, so an
+/// unreachable server isn't hammered, and goes back to normal once a request succeeds.
+fn poll<F, U>(
+    interval: Duration,
+    printing: Arc<AtomicBool>,
+    load: F,
+) -> impl Stream<Item = UiEvent, Error = UiError>
+where
+    F: FnMut() -> U + Clone,
+    U: Future<Item = Vec<UiEvent>, Error = OctoprintError>,
+{
+    let idle_interval = interval.max(Duration::from_secs(IDLE_POLL_INTERVAL));
+
+    // The wait before the next request, and the backoff if the last request failed
+    stream::unfold((Duration::from_secs(0), None), move |(wait, backoff)| {
+        let mut load = load.clone();
+        let printing = printing.clone();
+        let next = Delay::new(Instant::now() + wait)
+            .from_err::<UiError>()
+            .and_then(move |_| {
+                load().then(move |result| match result {
+                    Ok(events) => {
+                        let wait = if printing.load(Ordering::Relaxed) {
+                            interval
+                        } else {
+                            idle_interval
+                        };
+                        Ok((events, (wait, None)))
+                    }
+                    Err(e) => {
+                        let backoff = match backoff {
+                            Some(backoff) => BACKOFF_MAX.min(backoff * 2),
+                            None => BACKOFF_START,
+                        };
+                        Ok((
+                            vec![UiEvent::Error(UiError::from(e))],
+                            (backoff, Some(backoff)),
+                        ))
+                    }
+                })
+            });
+        Some(next)
+    })
+    .map(stream::iter_ok)
+    .flatten()
+}
+
+/// The client every task uses, which is replaced when switching profiles
+type SharedClient = Arc<RwLock<OctoprintClient>>;
+
+fn current_client(shared: &SharedClient) -> OctoprintClient {
+    shared.read().expect("Client lock poisoned").clone()
+}
+
+/// Points every task at another profile's server
+fn switch_profile(
+    shared: &SharedClient,
+    config: &Config,
+    index: usize,
+    timeout: Duration,
+    max_body_size: usize,
+) -> Result<(), String> {
+    let profile = config
+        .profiles
+        .get(index)
+        .ok_or(format!("No profile {}", index))?;
+    let url = profile
+        .url
+        .clone()
+        .or(config.url.clone())
+        .unwrap_or(DEFAULT_URL.to_string());
+    let api_key = profile
+        .api_key
+        .clone()
+        .or(config.api_key.clone())
+        .ok_or(format!("Profile {} has no API key", profile.name))?;
+    let auth = profile.auth.or(config.auth).unwrap_or(AuthScheme::ApiKey);
+
+    info!("Switching to profile {} at {}", profile.name, url);
+    let client = OctoprintClient::new(url, api_key, auth, timeout, max_body_size)
+        .map_err(|e| e.to_string())?;
+    *shared.write().expect("Client lock poisoned") = client;
+    Ok(())
+}
+
+/// Loads the job and printer state once and prints them as json or as one line
+fn print_status(mut octoprint: OctoprintClient, json: bool, units: TemperatureUnit) {
+    let mut state_client = octoprint.clone();
+    let load = octoprint.load_job().join(state_client.load_state());
+
+    let mut runtime = Runtime::new().unwrap();
+    match runtime.block_on(load) {
+        Ok((job, state)) if json => {
+            let status = JsonStatus { job, state };
+            let status = serde_json::to_string(&status).expect("Could not serialize status");
+            println!("{}", status);
+        }
+        Ok((job, state)) => println!("{}", status_line(&job, &state, units)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads the printer state once for --check, exiting with an error if it can't
+fn check_connection(mut octoprint: OctoprintClient) {
+    let mut runtime = Runtime::new().unwrap();
+    match runtime.block_on(octoprint.load_state()) {
+        Ok(state) => {
+            println!("OK");
+            match state.state {
+                Some(state) => println!("{}", state.text),
+                None => println!("No printer state"),
+            }
+        }
+        // OctoPrint only answers this once the API key is accepted
+        Err(OctoprintError::Http { status, .. }) if status == StatusCode::CONFLICT => {
+            println!("OK");
+            println!("Printer is not connected");
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-// Terminal is 65x177
+/// Prints the webcam stream URL for --webcam-url
+fn print_webcam_url(mut octoprint: OctoprintClient) {
+    let mut runtime = Runtime::new().unwrap();
+    match runtime.block_on(octoprint.load_settings()) {
+        Ok(settings) => match settings.webcam_stream_url() {
+            Some(url) => match octoprint.absolute_url(url) {
+                Ok(url) => println!("{}", url),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => {
+                eprintln!("No webcam is set up in OctoPrint");
+                std::process::exit(1);
+            }
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads the print history summary every few minutes, leaving failures to the log so they don't
+/// cover up the errors from the printer
+fn poll_history(
+    octoprint: SharedClient,
+    tx: mpsc::Sender<UiEvent>,
+) -> impl Future<Item = (), Error = ()> {
+    Interval::new(Instant::now(), HISTORY_INTERVAL)
+        .map_err(|e| error!("Error ticking history: {:?}", e))
+        .and_then(move |_| {
+            current_client(&octoprint)
+                .load_print_history()
+                .then(|result| match result {
+                    Ok(history) => Ok(Some(UiEvent::HistoryUpdate(
+                        history.map(|history| history.summary()),
+                    ))),
+                    Err(e) => {
+                        error!("Error getting print history: {}", e);
+                        Ok(None)
+                    }
+                })
+        })
+        .filter_map(|event| event)
+        .fold(tx, |tx, event| {
+            tx.send(event)
+                .map_err(|e| error!("Could not send event: {:?}", e))
+        })
+        .map(|_| ())
+}
+
+/// Loads a webcam snapshot every few seconds for the preview
+#[cfg(feature = "webcam")]
+fn poll_webcam(
+    octoprint: SharedClient,
+    tx: mpsc::Sender<UiEvent>,
+) -> impl Future<Item = (), Error = ()> {
+    Interval::new(Instant::now(), WEBCAM_INTERVAL)
+        .map_err(|e| error!("Error ticking webcam: {:?}", e))
+        .and_then(move |_| {
+            let mut client = current_client(&octoprint);
+            let mut snapshot_client = client.clone();
+            // The settings are loaded each time in case the webcam is changed in OctoPrint
+            client
+                .load_settings()
+                .and_then(move |settings| match settings.webcam_snapshot_url() {
+                    Some(url) => match snapshot_client.absolute_url(url) {
+                        Ok(url) => Either::A(snapshot_client.load_url(&url).map(Some)),
+                        Err(e) => Either::B(Err(e).into_future()),
+                    },
+                    None => Either::B(Ok(None).into_future()),
+                })
+                .then(|result| {
+                    let event = match result {
+                        Ok(Some(jpeg)) => match Snapshot::decode(&jpeg) {
+                            Ok(snapshot) => UiEvent::WebcamUpdate(Some(snapshot)),
+                            Err(e) => UiEvent::Error(UiError::Webcam(format!(
+                                "Could not decode webcam snapshot: {}",
+                                e
+                            ))),
+                        },
+                        Ok(None) => UiEvent::WebcamUpdate(None),
+                        Err(e) => UiEvent::Error(UiError::Webcam(format!(
+                            "Could not load webcam snapshot: {}",
+                            e
+                        ))),
+                    };
+                    Ok(event)
+                })
+        })
+        .fold(tx, |tx, event| {
+            tx.send(event)
+                .map_err(|e| error!("Could not send event: {:?}", e))
+        })
+        .map(|_| ())
+}
+
+/// Whether an event shows the update tasks are still running, even if their requests fail
+///
+/// Command errors come from key presses, so they don't show that polling is alive.
+fn is_heartbeat(event: &UiEvent) -> bool {
+    match event {
+        UiEvent::JobUpdate(_) | UiEvent::StateUpdate(_) | UiEvent::Polled => true,
+        UiEvent::Error(UiError::Poll(_)) => true,
+        _ => false,
+    }
+}
+
+/// Polls the job and printer state together, sending both updates to the ui each tick
+fn poll_printer(
+    octoprint: SharedClient,
+    tx: mpsc::Sender<UiEvent>,
+    poll_interval: Duration,
+    printing: Arc<AtomicBool>,
+) -> impl Future<Item = (), Error = ()> {
+    let state_printing = printing.clone();
+    poll(poll_interval, printing.clone(), move || {
+        let printing = state_printing.clone();
+        let mut client = current_client(&octoprint);
+        let mut state_client = client.clone();
+        // One after the other, so both go over the same kept alive connection
+        client.load_job().and_then(move |job_response| {
+            state_client.load_state().map(move |state_response| {
+                if let Some(state) = &state_response.state {
+                    printing.store(state.flags.printing, Ordering::Relaxed);
+                }
+                vec![
+                    UiEvent::JobUpdate(job_response),
+                    UiEvent::StateUpdate(state_response),
+                    UiEvent::Polled,
+                ]
+            })
+        })
+    })
+    // Told apart from the other requests' errors, since only these polls are counted
+    .map(|event| match event {
+        UiEvent::Error(UiError::Octoprint(e)) => UiEvent::Error(UiError::Poll(e)),
+        event => event,
+    })
+    .map_err(|e| error!("Error getting printer: {:?}", e))
+    .fold(tx, |tx, event| {
+        tx.send(event)
+            .map_err(|e| error!("Could not send event: {:?}", e))
+    })
+    .map(|_| ())
+}
+
+fn init_log(path: &Path) -> Result<(), Box<std::error::Error>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    WriteLogger::init(LevelFilter::Info, LogConfig::default(), file)?;
+    Ok(())
+}
 
 fn main() -> Result<(), Box<std::error::Error>> {
-    println!("Hello, world!");
+    let opt = Opt::from_args();
+
+    let env_url = env::var("OCTOPRINT_URL").ok();
+    let env_api_key = env::var("OCTOPRINT_API_KEY").ok();
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Kept whole for switching profiles, since parts of it are moved out below
+    let switch_config = config.clone();
+    // Losing the state only means starting on the overview again
+    let (saved_state, state_error) = match SavedState::load() {
+        Ok(saved_state) => (saved_state, None),
+        Err(e) => (SavedState::default(), Some(e)),
+    };
+
+    let profile = match opt.profile.as_ref() {
+        Some(name) => match config.find_profile(name) {
+            Some(index) => Some(index),
+            None => {
+                eprintln!("No profile named {} in the config file", name);
+                std::process::exit(1);
+            }
+        },
+        // Start with the last used profile, then the one in the config file, or the first if
+        // they have gone
+        None => saved_state
+            .profile
+            .as_ref()
+            .and_then(|name| config.find_profile(name))
+            .or_else(|| {
+                config
+                    .profile
+                    .as_ref()
+                    .and_then(|name| config.find_profile(name))
+            })
+            .or(if config.profiles.is_empty() {
+                None
+            } else {
+                Some(0)
+            }),
+    };
+    let (profile_url, profile_api_key, profile_auth) = match profile {
+        Some(index) => (
+            config.profiles[index].url.clone(),
+            config.profiles[index].api_key.clone(),
+            config.profiles[index].auth,
+        ),
+        None => (None, None, None),
+    };
+
+    // Settings are taken from, in order of precedence:
+    //  1. command line arguments
+    //  2. the OCTOPRINT_URL and OCTOPRINT_API_KEY environment variables
+    //  3. the selected profile in the config file
+    //  4. the rest of the config file
+    //  5. the built in defaults
+    let url = opt
+        .url
+        .or(env_url)
+        .or(profile_url)
+        .or(config.url)
+        .unwrap_or(DEFAULT_URL.to_string());
+    let api_key = match opt
+        .api_key
+        .or(env_api_key)
+        .or(profile_api_key)
+        .or(config.api_key)
+    {
+        Some(api_key) => api_key,
+        None => {
+            eprintln!(
+                "No API key given, pass one with --api-key, set OCTOPRINT_API_KEY, or set api_key in the config file"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let auth = opt
+        .auth
+        .or(profile_auth)
+        .or(config.auth)
+        .unwrap_or(AuthScheme::ApiKey);
+
+    let timeout = Duration::from_secs(opt.timeout.or(config.timeout).unwrap_or(DEFAULT_TIMEOUT));
+
+    let max_body_size = config.max_response_mb.unwrap_or(DEFAULT_MAX_RESPONSE_MB) * 1024 * 1024;
+
+    let octoprint = match OctoprintClient::new(url.clone(), api_key, auth, timeout, max_body_size) {
+        Ok(octoprint) => octoprint,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Status bars and scripts run these every few seconds, so they leave the log alone
+    if opt.json || opt.status_line {
+        let units = opt
+            .units
+            .or(config.units)
+            .unwrap_or(TemperatureUnit::Celsius);
+        print_status(octoprint, opt.json, units);
+        return Ok(());
+    }
+    if opt.check {
+        check_connection(octoprint);
+        return Ok(());
+    }
+    if opt.webcam_url {
+        print_webcam_url(octoprint);
+        return Ok(());
+    }
+    if opt.webcam && !cfg!(feature = "webcam") {
+        eprintln!("--webcam needs octoprint-tui built with the webcam feature");
+        std::process::exit(1);
+    }
+
+    // The terminal is taken over by the ui, so anything worth keeping goes to a file
+    if let Some(log_file) = opt.log_file.or(config.log_file).or_else(Config::log_path) {
+        if let Err(e) = init_log(&log_file) {
+            eprintln!("Could not open log file {}: {}", log_file.display(), e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(e) = state_error {
+        warn!("Ignoring the saved state: {}", e);
+    }
 
-    let url = "http://localhost:5000".to_string();
-    let api_key = "D8F72AC7BBCD4197889E4036B6ACA561".to_string();
+    info!("Connecting to {}", url);
+    let octoprint: SharedClient = Arc::new(RwLock::new(octoprint));
 
-    let mut octoprint = OctoprintClient::new(url, api_key);
+    // See https://no-color.org, which only counts NO_COLOR when it isn't empty
+    let color = !opt.no_color && !env::var("NO_COLOR").map_or(false, |v| !v.is_empty());
+    let theme = match Theme::load(config.theme.as_ref(), color) {
+        Ok(theme) => theme,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // The ui restores the terminal when it is dropped, but a panic message is printed before
+    // that happens, so make sure it can be read
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        print!(
+            "{}{}{}",
+            termion::clear::All,
+            termion::cursor::Goto(1, 1),
+            termion::cursor::Show
+        );
+        default_hook(info);
+    }));
+
+    // Bound before the terminal is taken over, so a port in use can be reported
+    let (metrics, metrics_server) = match opt.metrics_addr {
+        Some(addr) => {
+            let metrics = SharedMetrics::default();
+            match metrics::serve(addr, metrics.clone()) {
+                Ok(server) => (Some(metrics), Some(server)),
+                Err(e) => {
+                    eprintln!("Could not serve metrics on {}: {}", addr, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => (None, None),
+    };
 
     let stdout = io::stdout().into_raw_mode()?;
     let backend = TermionBackend::new(stdout);
     let terminal = Terminal::new(backend)?;
 
-    let mut ui = Ui::new(terminal);
-
     let mut runtime = Runtime::new().unwrap();
+    if let Some(server) = metrics_server {
+        runtime.spawn(server);
+    }
 
     let (tx, rx) = mpsc::channel(1024);
+    let (command_tx, command_rx) = mpsc::channel(16);
+
+    let poll_interval = Duration::from_secs(opt.poll_interval);
+
+    let ui_options = UiOptions {
+        jog_step: opt.jog_step.or(config.jog_step).unwrap_or(10.0),
+        babystep: opt.babystep.or(config.babystep).unwrap_or(0.05),
+        units: opt
+            .units
+            .or(config.units)
+            .unwrap_or(TemperatureUnit::Celsius),
+        poll_interval,
+        idle_poll_interval: Duration::from_secs(IDLE_POLL_INTERVAL),
+        notify_command: opt.notify_command.or(config.notify_command),
+        hooks: Hooks {
+            on_pause: config.on_pause,
+            on_resume: config.on_resume,
+            on_error: config.on_error,
+            on_complete: config.on_complete,
+        },
+        spool_length: opt.spool_length.or(config.spool_length),
+        show_inactive_tools: opt.show_inactive_tools,
+        profiles: config.profiles.iter().map(|p| p.name.clone()).collect(),
+        profile,
+        presets: if config.presets.is_empty() {
+            Preset::defaults()
+        } else {
+            config.presets.clone()
+        },
+        theme,
+        view: saved_state.view.unwrap_or(View::Overview),
+        webcam: opt.webcam,
+        idle_dim: config.idle_dim_secs.map(Duration::from_secs),
+        compact: opt.compact,
+        metrics,
+        temperature_format: TemperatureFormat {
+            precision: config.temperature_precision.unwrap_or(1),
+            decimal_separator: config.decimal_separator.unwrap_or('.'),
+        },
+        sections: Sections {
+            bed: config.show_bed.unwrap_or(true),
+            filament: config.show_filament.unwrap_or(true),
+            estimated: config.show_estimated.unwrap_or(true),
+            tuning: config.show_tuning.unwrap_or(true),
+        },
+    };
+
+    let mut ui = Ui::new(terminal, command_tx.clone(), ui_options);
+
+    let printing = Arc::new(AtomicBool::new(false));
+    let profile_generation = Arc::new(AtomicUsize::new(0));
+
+    let polling = poll_printer(
+        octoprint.clone(),
+        tx.clone(),
+        poll_interval,
+        printing.clone(),
+    );
+    // The push socket is a websocket, which needs a host rather than a Unix socket
+    if opt.no_push || current_client(&octoprint).is_unix() {
+        runtime.spawn(polling);
+    } else {
+        // Polling takes over if the push socket can't connect or drops
+        let push_events = tx.clone();
+        let fallback_events = tx.clone();
+        let push_generation = profile_generation.clone();
+        let push_printing = printing.clone();
+        let push_ended = tx.clone();
+        let push =
+            push::connect(&mut current_client(&octoprint)).then(move |result| match result {
+                Ok(events) => Either::A(
+                    stream::once(Ok(UiEvent::PushActive(true)))
+                        .chain(
+                            events
+                                // The socket is for the old printer once the profile changes
+                                .take_while(move |_| {
+                                    Ok(push_generation.load(Ordering::Relaxed) == 0)
+                                })
+                                .map_err(|e| error!("Error reading push socket: {}", e)),
+                        )
+                        // The other pollers and the watchdog pace themselves by the pushed state too
+                        .inspect(move |event| {
+                            if let UiEvent::StateUpdate(StateResponse {
+                                state: Some(state), ..
+                            }) = event
+                            {
+                                push_printing.store(state.flags.printing, Ordering::Relaxed);
+                            }
+                        })
+                        .fold(push_events, |tx, event| {
+                            tx.send(event)
+                                .map_err(|e| error!("Could not send event: {:?}", e))
+                        })
+                        .then(move |_| {
+                            push_ended
+                                .send(UiEvent::PushActive(false))
+                                .map_err(|e| error!("Could not send event: {:?}", e))
+                        })
+                        .then(|_| polling),
+                ),
+                Err(e) => Either::B({
+                    info!("Push socket unavailable, polling instead: {}", e);
+                    fallback_events
+                        .send(UiEvent::Error(UiError::from(e)))
+                        .map_err(|e| error!("Could not send event: {:?}", e))
+                        .and_then(|_| polling)
+                }),
+            });
+        runtime.spawn(push);
+    }
+
+    let connection_octoprint = octoprint.clone();
+    let update_connection = poll(poll_interval, printing.clone(), move || {
+        current_client(&connection_octoprint)
+            .load_connection()
+            .map(|connection| vec![UiEvent::ConnectionUpdate(connection)])
+    })
+    .map_err(|e| error!("Error getting connection: {:?}", e))
+    .fold(tx.clone(), |tx, event| {
+        tx.send(event)
+            .map_err(|e| error!("Could not send event: {:?}", e))
+    })
+    .map(|_| ());
+    runtime.spawn(update_connection);
+
+    let layer_octoprint = octoprint.clone();
+    let update_layers = poll(poll_interval, printing.clone(), move || {
+        current_client(&layer_octoprint)
+            .load_layer_progress()
+            .map(|layers| vec![UiEvent::LayerUpdate(layers)])
+    })
+    .map_err(|e| error!("Error getting layer progress: {:?}", e))
+    .fold(tx.clone(), |tx, event| {
+        tx.send(event)
+            .map_err(|e| error!("Could not send event: {:?}", e))
+    })
+    .map(|_| ());
+    runtime.spawn(update_layers);
+
+    let queue_octoprint = octoprint.clone();
+    let update_queue = poll(poll_interval, printing.clone(), move || {
+        current_client(&queue_octoprint)
+            .load_print_queue()
+            .map(|queue| vec![UiEvent::QueueUpdate(queue)])
+    })
+    .map_err(|e| error!("Error getting print queue: {:?}", e))
+    .fold(tx.clone(), |tx, event| {
+        tx.send(event)
+            .map_err(|e| error!("Could not send event: {:?}", e))
+    })
+    .map(|_| ());
+    runtime.spawn(update_queue);
+
+    let psu_octoprint = octoprint.clone();
+    let update_psu = poll(poll_interval, printing.clone(), move || {
+        current_client(&psu_octoprint)
+            .load_psu_state()
+            .map(|psu| vec![UiEvent::PsuUpdate(psu)])
+    })
+    .map_err(|e| error!("Error getting power state: {:?}", e))
+    .fold(tx.clone(), |tx, event| {
+        tx.send(event)
+            .map_err(|e| error!("Could not send event: {:?}", e))
+    })
+    .map(|_| ());
+    runtime.spawn(update_psu);
+
+    runtime.spawn(poll_history(octoprint.clone(), tx.clone()));
+
+    #[cfg(feature = "webcam")]
+    {
+        if opt.webcam {
+            runtime.spawn(poll_webcam(octoprint.clone(), tx.clone()));
+        }
+    }
 
-    let mut job_octoprint = octoprint.clone();
-    let update_job = Interval::new_interval(Duration::from_secs(1))
-        .map_err(UiError::from)
-        .and_then(move |now| job_octoprint.load_job().map_err(UiError::from))
-        .map_err(|e| eprintln!("Error getting jobs: {:?}", e))
-        .fold(tx.clone(), |tx, job_response| {
-            tx.send(UiEvent::JobUpdate(job_response))
-                .map_err(|e| eprintln!("Could not send event: {:?}", e))
+    let command_octoprint = octoprint.clone();
+    let command_events = tx.clone();
+    runtime.spawn(command_rx.for_each(move |command: Command| {
+        let events = command_events.clone();
+        let response = match command {
+            Command::SwitchProfile { index } => {
+                let event = match switch_profile(
+                    &command_octoprint,
+                    &switch_config,
+                    index,
+                    timeout,
+                    max_body_size,
+                ) {
+                    Ok(()) => {
+                        profile_generation.fetch_add(1, Ordering::Relaxed);
+                        UiEvent::ProfileSwitched(index)
+                    }
+                    Err(e) => UiEvent::Error(UiError::Profile(e)),
+                };
+                Box::new(Ok(vec![event]).into_future())
+            }
+            command => command.send(&mut current_client(&command_octoprint)),
+        };
+        response
+            .or_else(|e| Ok(vec![UiEvent::Error(UiError::from(e))]))
+            .and_then(move |responses| {
+                stream::iter_ok(responses)
+                    .fold(events, |tx, event| {
+                        tx.send(event)
+                            .map_err(|e| error!("Could not send event: {:?}", e))
+                    })
+                    .map(|_| ())
+            })
+    }));
+
+    let clock = Interval::new(Instant::now(), Duration::from_secs(1))
+        .map_err(|e| error!("Error ticking clock: {:?}", e))
+        .map(|_| UiEvent::Tick)
+        .fold(tx.clone(), |tx, event| {
+            tx.send(event)
+                .map_err(|e| error!("Could not send event: {:?}", e))
         })
         .map(|_| ());
-    runtime.spawn(update_job);
-
-    let mut state_octoprint = octoprint.clone();
-    let update_state = Interval::new_interval(Duration::from_secs(1))
-        .map_err(UiError::from)
-        .and_then(move |now| state_octoprint.load_state().map_err(UiError::from))
-        .map_err(|e| eprintln!("Error getting jobs: {:?}", e))
-        .fold(tx.clone(), |tx, state_response| {
-            tx.send(UiEvent::StateUpdate(state_response))
-                .map_err(|e| eprintln!("Could not send event: {:?}", e))
+    runtime.spawn(clock);
+
+    // Redraw as soon as the terminal changes size instead of waiting for the next poll
+    let mut last_size = termion::terminal_size().ok();
+    let resize = Interval::new(Instant::now(), RESIZE_POLL_INTERVAL)
+        .map_err(|e| error!("Error polling terminal size: {:?}", e))
+        .filter(move |_| {
+            let size = termion::terminal_size().ok();
+            let changed = size != last_size;
+            last_size = size;
+            changed
+        })
+        .map(|_| UiEvent::Resize)
+        .fold(tx.clone(), |tx, event| {
+            tx.send(event)
+                .map_err(|e| error!("Could not send event: {:?}", e))
         })
         .map(|_| ());
-    runtime.spawn(update_state);
+    runtime.spawn(resize);
 
-    runtime.spawn(rx.for_each(move |event| {
-        ui.draw(event);
-        Ok(())
-    }));
+    // The update tasks end on errors they can't recover from, which would otherwise leave the ui
+    // frozen on old values without saying why
+    let heartbeat = Arc::new(Mutex::new(Instant::now()));
+    let watchdog_heartbeat = heartbeat.clone();
+    let watchdog_printing = printing.clone();
+    let mut stalled = false;
+    let watchdog = Interval::new(Instant::now(), WATCHDOG_CHECK_INTERVAL)
+        .map_err(|e| error!("Error ticking watchdog: {:?}", e))
+        .filter_map(move |_| {
+            let expected = if watchdog_printing.load(Ordering::Relaxed) {
+                poll_interval
+            } else {
+                poll_interval.max(Duration::from_secs(IDLE_POLL_INTERVAL))
+            };
+            // Failing requests back off, but they still send errors so they aren't a stall
+            let limit = expected * WATCHDOG_MISSED_UPDATES + BACKOFF_MAX;
+            let age = watchdog_heartbeat
+                .lock()
+                .expect("Heartbeat lock poisoned")
+                .elapsed();
+
+            // Only report once per stall
+            let was_stalled = stalled;
+            stalled = age > limit;
+            if stalled && !was_stalled {
+                error!("No updates for {} seconds", age.as_secs());
+                Some(UiEvent::Error(UiError::Stalled(age)))
+            } else {
+                None
+            }
+        })
+        .fold(tx.clone(), |tx, event| {
+            tx.send(event)
+                .map_err(|e| error!("Could not send event: {:?}", e))
+        })
+        .map(|_| ());
+    runtime.spawn(watchdog);
 
-    iter(io::stdin().keys())
-        .map_err(|e| eprintln!("Key error: {:?}", e))
-        .filter(|k| *k == Key::Esc)
-        .into_future()
-        .wait();
+    // Reading stdin blocks, so keys are read on their own thread
+    let mut key_events = tx.clone();
+    thread::spawn(move || {
+        for key in io::stdin().keys() {
+            match key {
+                Ok(key) => match key_events.send(UiEvent::Key(key)).wait() {
+                    Ok(events) => key_events = events,
+                    Err(_) => break,
+                },
+                Err(e) => error!("Key error: {:?}", e),
+            }
+        }
+    });
+
+    // Runs until the ui asks to quit, then drops the ui which restores the terminal
+    let _ = runtime.block_on(rx.for_each(move |event| {
+        if is_heartbeat(&event) {
+            *heartbeat.lock().expect("Heartbeat lock poisoned") = Instant::now();
+        }
+        ui.handle(event);
+        ui.draw();
+        if ui.quitting() {
+            if let Err(e) = ui.saved_state().save() {
+                error!("Could not save state: {}", e);
+            }
+            Err(())
+        } else {
+            Ok(())
+        }
+    }));
 
     runtime.shutdown_now().wait().expect("Could not showdown");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeat_is_only_polling() {
+        let timeout = || OctoprintError::Timeout(Duration::from_secs(10));
+        assert!(is_heartbeat(&UiEvent::Polled));
+        assert!(is_heartbeat(&UiEvent::StateUpdate(
+            serde_json::from_str("{}").unwrap()
+        )));
+        assert!(is_heartbeat(&UiEvent::Error(UiError::Poll(timeout()))));
+        assert!(!is_heartbeat(&UiEvent::Error(
+            UiError::Octoprint(timeout())
+        )));
+        assert!(!is_heartbeat(&UiEvent::PushActive(true)));
+        assert!(!is_heartbeat(&UiEvent::Tick));
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use futures::Future;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::service_fn_ok;
+use hyper::Body;
+use hyper::Request;
+use hyper::Response;
+use hyper::Server;
+use hyper::StatusCode;
+use log::error;
+
+/// The latest readings from the ui, shared with the metrics server
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+/// The values served at /metrics, all in celsius whatever units the ui shows
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// Each hotend's index, temperature and target
+    pub tools: Vec<(usize, Option<f64>, Option<f64>)>,
+    pub bed_temp: Option<f64>,
+    pub bed_target: Option<f64>,
+    /// In percent
+    pub progress: Option<f64>,
+    /// In seconds
+    pub print_time_left: Option<f64>,
+}
+
+impl Metrics {
+    /// Writes the metrics in the Prometheus text format, leaving out values that aren't known
+    fn render(&self) -> String {
+        let mut text = String::new();
+        let mut gauge = |name: &str, help: &str, values: Vec<(String, f64)>| {
+            if values.is_empty() {
+                return;
+            }
+            writeln!(text, "# HELP {} {}", name, help).unwrap();
+            writeln!(text, "# TYPE {} gauge", name).unwrap();
+            for (labels, value) in values {
+                writeln!(text, "{}{} {}", name, labels, value).unwrap();
+            }
+        };
+
+        let tool = |index: usize| format!("{{tool=\"{}\"}}", index);
+        gauge(
+            "octoprint_tool_temperature_celsius",
+            "The hotend temperature",
+            self.tools
+                .iter()
+                .filter_map(|&(index, temp, _)| temp.map(|temp| (tool(index), temp)))
+                .collect(),
+        );
+        gauge(
+            "octoprint_tool_target_celsius",
+            "The hotend target temperature",
+            self.tools
+                .iter()
+                .filter_map(|&(index, _, target)| target.map(|target| (tool(index), target)))
+                .collect(),
+        );
+
+        let unlabeled =
+            |value: Option<f64>| value.map(|v| (String::new(), v)).into_iter().collect();
+        gauge(
+            "octoprint_bed_temperature_celsius",
+            "The bed temperature",
+            unlabeled(self.bed_temp),
+        );
+        gauge(
+            "octoprint_bed_target_celsius",
+            "The bed target temperature",
+            unlabeled(self.bed_target),
+        );
+        gauge(
+            "octoprint_progress_percent",
+            "How much of the print is done",
+            unlabeled(self.progress),
+        );
+        gauge(
+            "octoprint_print_time_left_seconds",
+            "OctoPrint's estimate of the time left in the print",
+            unlabeled(self.print_time_left),
+        );
+        text
+    }
+}
+
+/// Binds the metrics server, which serves the metrics once it is run on the runtime
+pub fn serve(
+    addr: SocketAddr,
+    metrics: SharedMetrics,
+) -> Result<impl Future<Item = (), Error = ()>, hyper::Error> {
+    let server = Server::try_bind(&addr)?.serve(move || {
+        let metrics = metrics.clone();
+        service_fn_ok(move |request: Request<Body>| {
+            let mut response = Response::builder();
+            if request.uri().path() != "/metrics" {
+                response.status(StatusCode::NOT_FOUND);
+                return response.body(Body::empty()).unwrap();
+            }
+            let text = metrics.lock().expect("Metrics lock poisoned").render();
+            response
+                .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(Body::from(text))
+                .unwrap()
+        })
+    });
+    Ok(server.map_err(|e| error!("Metrics server error: {}", e)))
+}
@@ -1,29 +1,100 @@
+use std::collections::HashMap;
+use std::fmt;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+
+use chrono::DateTime;
+use chrono::Datelike;
+use chrono::Local;
+
+use futures::future;
+use futures::future::Either;
 use futures::Future;
 use futures::Stream;
 
+#[cfg(unix)]
+use hyper::client::connect::{Connect, Connected, Destination};
 use hyper::client::HttpConnector;
+use hyper::client::ResponseFuture;
+use hyper::header::CONTENT_LENGTH;
+use hyper::http::request;
 use hyper::Body;
+use hyper::Chunk;
 use hyper::Client;
+use hyper::Method;
 use hyper::Request;
+use hyper::StatusCode;
+use hyper::Uri;
+use hyper_tls::HttpsConnector;
+use percent_encoding::utf8_percent_encode;
+use percent_encoding::PATH_SEGMENT_ENCODE_SET;
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use tokio_timer::Timeout;
+#[cfg(unix)]
+use tokio_uds::UnixStream;
+use websocket::client::ParseError;
+use websocket::WebSocketError;
 
-#[derive(Deserialize, Debug, Clone)]
+/// Deserializes null the same as a missing field, for use with `#[serde(default)]`
+pub fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Option::<T>::deserialize(deserializer).map(Option::unwrap_or_default)
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(from = "String", into = "String")]
 pub enum Origin {
-    #[serde(rename = "local")]
     Local,
-    #[serde(rename = "sdcard")]
     SdCard,
+    /// An origin from a newer OctoPrint, kept as sent so it doesn't break the whole response
+    Other(String),
 }
 
-#[derive(Deserialize, Debug, Clone)]
+impl From<String> for Origin {
+    fn from(origin: String) -> Origin {
+        match origin.as_str() {
+            "local" => Origin::Local,
+            "sdcard" => Origin::SdCard,
+            _ => Origin::Other(origin),
+        }
+    }
+}
+
+impl From<Origin> for String {
+    fn from(origin: Origin) -> String {
+        origin.to_string()
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Local => write!(f, "local"),
+            Origin::SdCard => write!(f, "sdcard"),
+            Origin::Other(origin) => write!(f, "{}", origin),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct References {
     pub resource: String,
     pub download: Option<String>,
     pub model: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct FileAbridged {
     pub name: Option<String>,
     pub display: Option<String>,
@@ -32,14 +103,96 @@ pub struct FileAbridged {
     pub references: Option<References>,
 }
 
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub enum FileType {
+    #[serde(rename = "machinecode")]
+    MachineCode,
+    #[serde(rename = "model")]
+    Model,
+    #[serde(rename = "folder")]
+    Folder,
+}
+
 #[derive(Deserialize, Debug, Clone)]
+pub struct FileInfo {
+    pub name: String,
+    pub display: Option<String>,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: FileType,
+    pub origin: Origin,
+    pub size: Option<u64>,
+    pub date: Option<u64>,
+    pub refs: Option<References>,
+    pub children: Option<Vec<FileInfo>>,
+    /// Only sent once OctoPrint has analysed the file
+    #[serde(rename = "gcodeAnalysis")]
+    pub gcode_analysis: Option<GcodeAnalysis>,
+    /// From the Slicer Settings Parser plugin, named as the slicer names them
+    pub slicer_settings: Option<HashMap<String, serde_json::Value>>,
+}
+
+impl FileInfo {
+    /// The layer height in mm the file was sliced with
+    pub fn layer_height(&self) -> Option<f64> {
+        self.slicer_setting(&["layer_height"])
+    }
+
+    /// The hotend temperature in °C the file was sliced for
+    pub fn nozzle_temperature(&self) -> Option<f64> {
+        // PrusaSlicer and Slic3r, then Cura
+        self.slicer_setting(&["temperature", "material_print_temperature"])
+    }
+
+    /// The first of the settings that is a number, using the first tool's value of settings
+    /// like `215,215` that have one per tool
+    fn slicer_setting(&self, names: &[&str]) -> Option<f64> {
+        let settings = self.slicer_settings.as_ref()?;
+        names
+            .iter()
+            .filter_map(|name| match settings.get(*name)? {
+                serde_json::Value::Number(n) => n.as_f64(),
+                serde_json::Value::String(s) => s.split(',').next()?.trim().parse().ok(),
+                _ => None,
+            })
+            .next()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Dimensions {
+    pub width: Option<f64>,
+    pub depth: Option<f64>,
+    pub height: Option<f64>,
+}
+
+/// What OctoPrint works out from reading through the G-code
+#[derive(Deserialize, Debug, Clone)]
+pub struct GcodeAnalysis {
+    #[serde(rename = "estimatedPrintTime")]
+    pub estimated_print_time: Option<f64>,
+    /// The filament each tool uses, by tool name like `tool0`
+    pub filament: Option<HashMap<String, Filament>>,
+    pub dimensions: Option<Dimensions>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FilesResponse {
+    pub files: Vec<FileInfo>,
+    pub free: Option<u64>,
+    pub total: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Filament {
     pub length: Option<f64>,
     pub volume: Option<f64>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Job {
+    /// Has every field null when no file is selected, or may be missing entirely
+    #[serde(default, deserialize_with = "null_as_default")]
     pub file: FileAbridged,
     #[serde(rename = "estimatedPrintTime")]
     pub estimated_print_time: Option<f64>,
@@ -48,7 +201,7 @@ pub struct Job {
     pub filament: Option<Filament>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Progress {
     pub completion: Option<f64>,
     pub filepos: Option<f64>,
@@ -58,37 +211,47 @@ pub struct Progress {
     pub print_time_left: Option<f64>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TemperatureData {
+    /// Null when the heater is disconnected
+    #[serde(default, deserialize_with = "null_as_default")]
     pub actual: f64,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub target: f64,
     pub offset: Option<f64>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct HistoricTemperatureData {
+    #[serde(default)]
     pub time: u64,
     pub tool0: Option<TemperatureData>,
     pub tool1: Option<TemperatureData>,
     pub tool2: Option<TemperatureData>,
     pub bed: Option<TemperatureData>,
+    pub chamber: Option<TemperatureData>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TemperatureState {
     pub tool0: Option<TemperatureData>,
     pub tool1: Option<TemperatureData>,
     pub tool2: Option<TemperatureData>,
     pub bed: Option<TemperatureData>,
+    /// Only reported by enclosed printers with a chamber heater or sensor
+    pub chamber: Option<TemperatureData>,
     pub history: Option<Vec<HistoricTemperatureData>>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct SdState {
+    #[serde(default)]
     pub ready: bool,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// Older versions of OctoPrint don't send every flag, so missing ones are false
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
 pub struct PrinterFlags {
     pub operational: bool,
     pub paused: bool,
@@ -103,29 +266,464 @@ pub struct PrinterFlags {
     pub closed_or_error: bool,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct PrinterState {
+    #[serde(default)]
     pub text: String,
+    #[serde(default, deserialize_with = "null_as_default")]
     pub flags: PrinterFlags,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct StateResponse {
     pub temperature: Option<TemperatureState>,
     pub sd: Option<SdState>,
     pub state: Option<PrinterState>,
+    /// The current Z height, which OctoPrint only reports in its push updates
+    #[serde(rename = "currentZ")]
+    pub current_z: Option<f64>,
+}
+
+/// The layer counts from the DisplayLayerProgress plugin, which sends them as strings like `"42"`,
+/// or `"-"` before the print reaches the first layer
+#[derive(Deserialize, Debug, Clone)]
+pub struct LayerValues {
+    pub current: Option<serde_json::Value>,
+    pub total: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DisplayLayerProgressResponse {
+    pub layer: Option<LayerValues>,
+}
+
+/// How many layers of the print are done
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayerProgress {
+    pub current: u32,
+    pub total: u32,
+}
+
+impl DisplayLayerProgressResponse {
+    /// The layer counts, if the plugin knows both
+    pub fn layer_progress(&self) -> Option<LayerProgress> {
+        let layer = self.layer.as_ref()?;
+        let count = |value: &Option<serde_json::Value>| match value.as_ref()? {
+            serde_json::Value::Number(n) => n.as_u64().map(|n| n as u32),
+            serde_json::Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        };
+        Some(LayerProgress {
+            current: count(&layer.current)?,
+            total: count(&layer.total)?,
+        })
+    }
+}
+
+/// The queues from the Continuous Print plugin
+#[derive(Deserialize, Debug, Clone)]
+pub struct ContinuousPrintState {
+    /// Whether the plugin is working through the queue
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub queues: Vec<PrintQueue>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
+pub struct PrintQueue {
+    pub name: String,
+    #[serde(default)]
+    pub jobs: Vec<QueuedJob>,
+}
+
+/// A job in a print queue, which prints its files some number of times
+#[derive(Deserialize, Debug, Clone)]
+pub struct QueuedJob {
+    pub name: String,
+    #[serde(default)]
+    pub count: u32,
+    #[serde(default)]
+    pub remaining: u32,
+    /// Whether the job is the one being printed
+    #[serde(default)]
+    pub acquired: bool,
+    #[serde(default)]
+    pub sets: Vec<QueuedSet>,
+}
+
+/// A file in a queued job and how many more times to print it
+#[derive(Deserialize, Debug, Clone)]
+pub struct QueuedSet {
+    pub path: String,
+    #[serde(default)]
+    pub count: u32,
+    #[serde(default)]
+    pub remaining: u32,
+}
+
+/// The finished prints recorded by the Print History plugin
+#[derive(Deserialize, Debug, Clone)]
+pub struct PrintHistoryResponse {
+    #[serde(default)]
+    pub history: Vec<HistoricPrint>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct HistoricPrint {
+    #[serde(default)]
+    pub success: bool,
+    /// When the print finished, in seconds since the epoch
+    #[serde(default)]
+    pub timestamp: f64,
+    /// In seconds
+    #[serde(rename = "printTime", default)]
+    pub print_time: f64,
+}
+
+/// What the print history adds up to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistorySummary {
+    pub prints_today: usize,
+    /// How long today's prints took, in seconds
+    pub print_time_today: f64,
+    pub successes: usize,
+    pub failures: usize,
+}
+
+impl PrintHistoryResponse {
+    /// Counts today's prints in local time, and the successes and failures of all of them
+    pub fn summary(&self) -> HistorySummary {
+        let now = Local::now();
+        let prints_today: Vec<&HistoricPrint> = self
+            .history
+            .iter()
+            .filter(|print| {
+                let finished = UNIX_EPOCH + Duration::from_secs(print.timestamp.max(0.0) as u64);
+                let finished = DateTime::<Local>::from(finished);
+                (finished.year(), finished.ordinal()) == (now.year(), now.ordinal())
+            })
+            .collect();
+        let successes = self.history.iter().filter(|print| print.success).count();
+        HistorySummary {
+            prints_today: prints_today.len(),
+            print_time_today: prints_today.iter().map(|print| print.print_time).sum(),
+            successes,
+            failures: self.history.len() - successes,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConnectionState {
+    #[serde(default)]
+    pub state: String,
+    pub port: Option<String>,
+    pub baudrate: Option<u32>,
+    #[serde(rename = "printerProfile")]
+    pub printer_profile: Option<String>,
+}
+
+/// The ports and baudrates the printer can be connected with
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ConnectionOptions {
+    #[serde(default)]
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub baudrates: Vec<u32>,
+    #[serde(rename = "portPreference")]
+    pub port_preference: Option<String>,
+    #[serde(rename = "baudratePreference")]
+    pub baudrate_preference: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ConnectionResponse {
+    pub current: ConnectionState,
+    pub options: Option<ConnectionOptions>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct JobResponse {
+    #[serde(default, deserialize_with = "null_as_default")]
     pub job: Job,
+    /// Left out or null by some OctoPrint versions when no file is selected
+    #[serde(default, deserialize_with = "null_as_default")]
     pub progress: Progress,
 }
 
+/// The Bed Visualizer plugin's settings, which hold the last probed mesh
+#[derive(Deserialize, Debug, Clone)]
+pub struct BedLevelVisualizerSettings {
+    /// Rows of Z offsets, which the plugin may store as numbers or strings
+    pub stored_mesh: Option<Vec<Vec<serde_json::Value>>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PluginSettings {
+    pub bedlevelvisualizer: Option<BedLevelVisualizerSettings>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebcamSettings {
+    #[serde(rename = "webcamEnabled")]
+    pub webcam_enabled: Option<bool>,
+    /// Often relative to the server, like `/webcam/?action=stream`
+    #[serde(rename = "streamUrl")]
+    pub stream_url: Option<String>,
+    #[serde(rename = "snapshotUrl")]
+    pub snapshot_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SettingsResponse {
+    pub plugins: Option<PluginSettings>,
+    pub webcam: Option<WebcamSettings>,
+}
+
+impl SettingsResponse {
+    /// The webcam stream URL as OctoPrint has it, if a webcam is set up and enabled
+    pub fn webcam_stream_url(&self) -> Option<&str> {
+        self.webcam_url(|webcam| &webcam.stream_url)
+    }
+
+    /// The webcam snapshot URL as OctoPrint has it, if a webcam is set up and enabled
+    #[cfg(feature = "webcam")]
+    pub fn webcam_snapshot_url(&self) -> Option<&str> {
+        self.webcam_url(|webcam| &webcam.snapshot_url)
+    }
+
+    fn webcam_url<F>(&self, url: F) -> Option<&str>
+    where
+        F: Fn(&WebcamSettings) -> &Option<String>,
+    {
+        let webcam = self.webcam.as_ref()?;
+        if webcam.webcam_enabled == Some(false) {
+            return None;
+        }
+        url(webcam)
+            .as_ref()
+            .map(|url| url.trim())
+            .filter(|url| !url.is_empty())
+    }
+
+    /// The bed mesh as rows of Z offsets, if the Bed Visualizer plugin has probed one
+    pub fn bed_mesh(&self) -> Option<Vec<Vec<f64>>> {
+        let mesh = self
+            .plugins
+            .as_ref()?
+            .bedlevelvisualizer
+            .as_ref()?
+            .stored_mesh
+            .as_ref()?;
+        let mesh: Option<Vec<Vec<f64>>> = mesh
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| match value {
+                        serde_json::Value::Number(n) => n.as_f64(),
+                        serde_json::Value::String(s) => s.trim().parse().ok(),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        mesh.filter(|mesh| !mesh.is_empty() && mesh.iter().all(|row| !row.is_empty()))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoginResponse {
+    pub name: String,
+    pub session: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PauseAction {
+    Pause,
+    Resume,
+    Toggle,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum JobCommand {
+    Start,
+    Cancel,
+    Restart,
+    Pause { action: PauseAction },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum FileCommand {
+    Select { print: bool },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum ToolCommand {
+    Target { targets: HashMap<String, f64> },
+    Offset { offsets: HashMap<String, f64> },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum BedCommand {
+    Target { target: f64 },
+    Offset { offset: f64 },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum PrinterAxis {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum PrintheadCommand {
+    Jog { x: f64, y: f64, z: f64 },
+    Home { axes: Vec<PrinterAxis> },
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum ConnectionCommand {
+    /// OctoPrint picks the port or baudrate itself when one is left out
+    Connect {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        port: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        baudrate: Option<u32>,
+    },
+    Disconnect,
+}
+
+/// Commands for the PSU Control plugin
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "command")]
+pub enum PsuCommand {
+    #[serde(rename = "turnPSUOn")]
+    TurnOn,
+    #[serde(rename = "turnPSUOff")]
+    TurnOff,
+    #[serde(rename = "getPSUState")]
+    GetState,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PsuStateResponse {
+    #[serde(rename = "isPSUOn")]
+    pub is_psu_on: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GcodeCommands {
+    pub commands: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LoginCommand {
+    pub passive: bool,
+}
+
 #[derive(Debug)]
 pub enum OctoprintError {
     Network(hyper::Error),
-    Parse(serde_json::Error),
+    /// A response that didn't match the expected json, with where it came from and the
+    /// part of the body around the error
+    Parse {
+        source: String,
+        context: String,
+        err: serde_json::Error,
+    },
+    /// A server URL that can't be used, with why
+    Uri {
+        url: String,
+        reason: String,
+    },
+    /// A request that couldn't be built, like one for a path that isn't a valid URL
+    BadRequest(hyper::http::Error),
+    Http {
+        status: StatusCode,
+        body: String,
+    },
+    /// A 403, when the api key is wrong or its user isn't allowed to do something, with the path
+    /// it was for and OctoPrint's reason if it gave one
+    Forbidden {
+        path: String,
+        reason: Option<String>,
+    },
+    Url(ParseError),
+    WebSocket(WebSocketError),
+    /// No response came within the timeout
+    Timeout(Duration),
+    /// A response body bigger than the limit in bytes, with the path it was for
+    TooLarge {
+        path: String,
+        limit: usize,
+    },
+    Timer(tokio_timer::Error),
+}
+
+impl fmt::Display for OctoprintError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OctoprintError::Network(err) => write!(f, "Network error: {}", err),
+            OctoprintError::Parse {
+                source,
+                context,
+                err,
+            } => write!(f, "Could not parse {}: {} near `{}`", source, err, context),
+            OctoprintError::Uri { url, reason } => {
+                write!(f, "Invalid server URL {}: {}", url, reason)
+            }
+            OctoprintError::BadRequest(err) => write!(f, "Could not build request: {}", err),
+            OctoprintError::Http { status, body } => write!(f, "HTTP {}: {}", status, body),
+            OctoprintError::Forbidden { path, reason } => {
+                write!(f, "Invalid key or insufficient permissions for {}", path)?;
+                match reason {
+                    Some(reason) => write!(f, ": {}", reason),
+                    None => Ok(()),
+                }
+            }
+            OctoprintError::Url(err) => write!(f, "Invalid URL: {}", err),
+            OctoprintError::WebSocket(err) => write!(f, "Push socket error: {}", err),
+            OctoprintError::Timeout(timeout) => {
+                write!(f, "No response after {} seconds", timeout.as_secs())
+            }
+            OctoprintError::TooLarge { path, limit } => write!(
+                f,
+                "The response from {} is over the {} MB limit",
+                path,
+                limit / (1024 * 1024)
+            ),
+            OctoprintError::Timer(err) => write!(f, "Timer error: {}", err),
+        }
+    }
+}
+
+impl OctoprintError {
+    /// A short name for what went wrong, without the details
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OctoprintError::Network(_) => "network",
+            OctoprintError::Parse { .. } => "parse",
+            OctoprintError::Uri { .. } => "server url",
+            OctoprintError::BadRequest(_) => "bad request",
+            OctoprintError::Http { .. } => "http",
+            OctoprintError::Forbidden { .. } => "forbidden",
+            OctoprintError::Url(_) => "url",
+            OctoprintError::WebSocket(_) => "push socket",
+            OctoprintError::Timeout(_) => "timeout",
+            OctoprintError::TooLarge { .. } => "too large",
+            OctoprintError::Timer(_) => "timer",
+        }
+    }
 }
 
 impl From<hyper::Error> for OctoprintError {
@@ -134,50 +732,443 @@ impl From<hyper::Error> for OctoprintError {
     }
 }
 
-impl From<serde_json::Error> for OctoprintError {
-    fn from(err: serde_json::Error) -> OctoprintError {
-        OctoprintError::Parse(err)
+impl From<hyper::http::Error> for OctoprintError {
+    fn from(err: hyper::http::Error) -> OctoprintError {
+        OctoprintError::BadRequest(err)
+    }
+}
+
+impl From<ParseError> for OctoprintError {
+    fn from(err: ParseError) -> OctoprintError {
+        OctoprintError::Url(err)
+    }
+}
+
+impl From<WebSocketError> for OctoprintError {
+    fn from(err: WebSocketError) -> OctoprintError {
+        OctoprintError::WebSocket(err)
+    }
+}
+
+/// How the api key is sent to the server
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthScheme {
+    /// An `X-Api-Key: KEY` header, which OctoPrint itself expects
+    ApiKey,
+    /// An `Authorization: Bearer KEY` header, for gateways in front of OctoPrint
+    Bearer,
+}
+
+impl FromStr for AuthScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AuthScheme, String> {
+        match s {
+            "api-key" => Ok(AuthScheme::ApiKey),
+            "bearer" => Ok(AuthScheme::Bearer),
+            _ => Err(format!(
+                "Unknown auth scheme '{}', use api-key or bearer",
+                s
+            )),
+        }
+    }
+}
+
+/// Connects to a Unix socket whatever the host in the URI, since the socket is the server
+#[cfg(unix)]
+#[derive(Clone)]
+struct UnixConnector(PathBuf);
+
+#[cfg(unix)]
+impl Connect for UnixConnector {
+    type Transport = UnixStream;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = (UnixStream, Connected), Error = io::Error> + Send>;
+
+    fn connect(&self, _: Destination) -> Self::Future {
+        Box::new(UnixStream::connect(&self.0).map(|stream| (stream, Connected::new())))
     }
 }
 
+/// Sends requests over the network, or to a Unix socket when OctoPrint is on the same machine
+#[derive(Clone)]
+enum HttpClient {
+    Tcp(Client<HttpsConnector<HttpConnector>, Body>),
+    #[cfg(unix)]
+    Unix(Client<UnixConnector, Body>),
+}
+
+impl HttpClient {
+    fn request(&self, request: Request<Body>) -> ResponseFuture {
+        match self {
+            HttpClient::Tcp(client) => client.request(request),
+            #[cfg(unix)]
+            HttpClient::Unix(client) => client.request(request),
+        }
+    }
+}
+
+/// The start of a server URL for a Unix socket, like `unix:///run/octoprint.sock`
+const UNIX_SCHEME: &str = "unix://";
+
 #[derive(Clone)]
 pub struct OctoprintClient {
-    client: Client<HttpConnector, Body>,
+    client: HttpClient,
     url: String,
+    /// What request URIs start with, which is a placeholder host for a Unix socket
+    request_url: String,
     api_key: String,
+    auth: AuthScheme,
+    timeout: Duration,
+    /// The biggest response body read, in bytes, so a huge file list can't use up the memory
+    max_body_size: usize,
 }
 
 impl OctoprintClient {
-    pub fn new(url: String, api_key: String) -> OctoprintClient {
-        let client = Client::new();
-        OctoprintClient {
-            client,
+    /// Makes a client for the server at `url`, failing if it isn't an http, https or unix URL
+    pub fn new(
+        url: String,
+        api_key: String,
+        auth: AuthScheme,
+        timeout: Duration,
+        max_body_size: usize,
+    ) -> Result<OctoprintClient, OctoprintError> {
+        let url = base_url(&url);
+        if url.starts_with(UNIX_SCHEME) {
+            return OctoprintClient::new_unix(url, api_key, auth, timeout, max_body_size);
+        }
+
+        let invalid = |reason: String| OctoprintError::Uri {
+            url: url.clone(),
+            reason,
+        };
+        let uri = url.parse::<Uri>().map_err(|err| invalid(err.to_string()))?;
+        match uri.scheme_str() {
+            Some("http") | Some("https") => {}
+            _ => {
+                return Err(invalid(
+                    "it must start with http://, https:// or unix://".to_string(),
+                ))
+            }
+        }
+        if uri.host().is_none() {
+            return Err(invalid("it has no host".to_string()));
+        }
+
+        let https = HttpsConnector::new(4).expect("Could not initialize TLS");
+        let client = Client::builder().build::<_, Body>(https);
+        Ok(OctoprintClient {
+            client: HttpClient::Tcp(client),
+            request_url: url.clone(),
             url,
             api_key,
+            auth,
+            timeout,
+            max_body_size,
+        })
+    }
+
+    /// Makes a client for a server listening on a Unix socket, like `unix:///run/octoprint.sock`
+    #[cfg(unix)]
+    fn new_unix(
+        url: String,
+        api_key: String,
+        auth: AuthScheme,
+        timeout: Duration,
+        max_body_size: usize,
+    ) -> Result<OctoprintClient, OctoprintError> {
+        let socket = PathBuf::from(&url[UNIX_SCHEME.len()..]);
+        if !socket.is_absolute() {
+            return Err(OctoprintError::Uri {
+                url,
+                reason: "the socket must be an absolute path, like unix:///run/octoprint.sock"
+                    .to_string(),
+            });
         }
+
+        let client = Client::builder().build::<_, Body>(UnixConnector(socket));
+        Ok(OctoprintClient {
+            client: HttpClient::Unix(client),
+            request_url: "http://localhost".to_string(),
+            url,
+            api_key,
+            auth,
+            timeout,
+            max_body_size,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new_unix(
+        url: String,
+        _: String,
+        _: AuthScheme,
+        _: Duration,
+        _: usize,
+    ) -> Result<OctoprintClient, OctoprintError> {
+        Err(OctoprintError::Uri {
+            url,
+            reason: "Unix sockets aren't supported on this platform".to_string(),
+        })
+    }
+
+    /// Starts a request to the api with the authentication headers set
+    fn request_builder(&self, method: Method, path: &str) -> request::Builder {
+        self.server_request_builder(method, &format!("api/{}", path))
+    }
+
+    /// Whether the client talks to a Unix socket, which has no push socket and no host for
+    /// relative URLs
+    pub fn is_unix(&self) -> bool {
+        self.url.starts_with(UNIX_SCHEME)
+    }
+
+    /// Makes a URL from the settings absolute, since URLs like the webcam stream are usually
+    /// relative to the server
+    pub fn absolute_url(&self, url: &str) -> Result<String, OctoprintError> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(url.to_string());
+        }
+        if self.is_unix() {
+            return Err(OctoprintError::Uri {
+                url: url.to_string(),
+                reason: "relative URLs can't be resolved for a server on a Unix socket".to_string(),
+            });
+        }
+        // A leading slash is from the root of the host, even when OctoPrint is under a path
+        let base = self.url.parse::<Uri>().ok();
+        match base
+            .as_ref()
+            .and_then(|b| Some((b.scheme_part()?, b.authority_part()?)))
+        {
+            Some((scheme, authority)) if url.starts_with('/') => {
+                Ok(format!("{}://{}{}", scheme, authority, url))
+            }
+            _ => Ok(format!("{}/{}", self.url, url.trim_start_matches('/'))),
+        }
+    }
+
+    /// Builds a request for a path on the server outside of the api, like a plugin's own routes
+    fn server_request_builder(&self, method: Method, path: &str) -> request::Builder {
+        let mut builder = Request::builder();
+        builder
+            .method(method)
+            .uri(format!("{}/{}", self.request_url, path));
+        match self.auth {
+            AuthScheme::ApiKey => builder.header("X-Api-Key", self.api_key.clone()),
+            AuthScheme::Bearer => {
+                builder.header("Authorization", format!("Bearer {}", self.api_key))
+            }
+        };
+        builder
+    }
+
+    /// Builds a request with a json body
+    fn json_request<C: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        command: &C,
+    ) -> Result<Request<Body>, OctoprintError> {
+        let body = serde_json::to_vec(command).expect("Could not serialize command");
+        Ok(self
+            .request_builder(method, path)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))?)
+    }
+
+    /// Sends a request, resolving to the whole body if OctoPrint responds with a 2xx status
+    ///
+    /// A server that never answers fails with a timeout instead of hanging forever, and a body
+    /// bigger than `max_body_size` bytes fails with `TooLarge` rather than being read into
+    /// memory.
+    fn send(
+        &self,
+        request: Result<Request<Body>, OctoprintError>,
+    ) -> impl Future<Item = Chunk, Error = OctoprintError> {
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return Either::A(future::err(e)),
+        };
+        let timeout = self.timeout;
+        let limit = self.max_body_size;
+        let path = request.uri().path().to_string();
+        let response = self
+            .client
+            .request(request)
+            .from_err::<OctoprintError>()
+            .and_then(move |res| {
+                let status = res.status();
+                let too_large = {
+                    let path = path.clone();
+                    move || OctoprintError::TooLarge {
+                        path: path.clone(),
+                        limit,
+                    }
+                };
+
+                // Give up before reading anything when the server says it is too big
+                let length = res
+                    .headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|length| length.to_str().ok())
+                    .and_then(|length| length.parse::<usize>().ok());
+                if length.map(|length| length > limit).unwrap_or(false) {
+                    return Either::A(future::err(too_large()));
+                }
+
+                // Chunked bodies have no length, so the size is checked as they arrive
+                let body = res.into_body().from_err::<OctoprintError>().fold(
+                    Vec::new(),
+                    move |mut body, chunk| {
+                        if body.len() + chunk.len() > limit {
+                            return Err(too_large());
+                        }
+                        body.extend_from_slice(&chunk);
+                        Ok(body)
+                    },
+                );
+                Either::B(body.and_then(move |body| {
+                    // Error responses are plain text or html, so don't try to parse them
+                    if status.is_success() {
+                        Ok(Chunk::from(body))
+                    } else if status == StatusCode::FORBIDDEN {
+                        Err(forbidden(path, &body))
+                    } else {
+                        Err(OctoprintError::Http {
+                            status,
+                            body: error_body(&body),
+                        })
+                    }
+                }))
+            });
+        Either::B(Timeout::new(response, timeout).map_err(move |err| {
+            if err.is_elapsed() {
+                OctoprintError::Timeout(timeout)
+            } else if err.is_timer() {
+                OctoprintError::Timer(err.into_timer().expect("Timer error without a timer error"))
+            } else {
+                err.into_inner()
+                    .expect("Timeout error without an inner error")
+            }
+        }))
     }
 
     fn send_request<R: DeserializeOwned>(
         &self,
         path: String,
     ) -> impl Future<Item = R, Error = OctoprintError> {
-        let request = Request::builder()
-            .uri(format!("{}/api/{}", self.url.clone(), path))
-            .header("X-Api-Key", self.api_key.clone())
+        let request = self
+            .request_builder(Method::GET, &path)
             .body(Body::empty())
-            .expect(&format!(
-                "Error building reqest with url {}, api_key {}, and path {}",
-                self.url, self.api_key, path
-            ));
-        self.client
-            .request(request)
-            .and_then(|res| res.into_body().concat2())
-            .from_err::<OctoprintError>()
-            .and_then(|body| {
-                let job = serde_json::from_slice(&body)?;
-                Ok(job)
+            .map_err(OctoprintError::from);
+        self.send(request)
+            .and_then(move |body| parse_json(&format!("/api/{}", path), &body))
+    }
+
+    /// POSTs a json command to the api, succeeding if OctoPrint responds with a 2xx status
+    fn send_command<C: Serialize>(
+        &self,
+        path: String,
+        command: &C,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        let request = self.json_request(Method::POST, &path, command);
+        self.send(request).map(|_| ())
+    }
+
+    /// Loads the body at an absolute URL, like a webcam snapshot, without the api key since the
+    /// URL may be for another server. A Unix socket client can only reach OctoPrint itself.
+    #[cfg(feature = "webcam")]
+    pub fn load_url(&mut self, url: &str) -> impl Future<Item = Chunk, Error = OctoprintError> {
+        let request = if self.is_unix() {
+            Err(OctoprintError::Uri {
+                url: url.to_string(),
+                reason: "it can't be loaded through a Unix socket".to_string(),
             })
-            .from_err()
+        } else {
+            Request::get(url)
+                .body(Body::empty())
+                .map_err(OctoprintError::from)
+        };
+        self.send(request)
+    }
+
+    /// The base URL of the OctoPrint server
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Starts a passive login with the api key, giving a session for the push socket
+    pub fn login(&mut self) -> impl Future<Item = LoginResponse, Error = OctoprintError> {
+        let request = self.json_request(Method::POST, "login", &LoginCommand { passive: true });
+        self.send(request)
+            .and_then(|body| parse_json("/api/login", &body))
+    }
+
+    /// Loads the layer counts from the DisplayLayerProgress plugin, or none if it isn't installed
+    pub fn load_layer_progress(
+        &mut self,
+    ) -> impl Future<Item = Option<LayerProgress>, Error = OctoprintError> {
+        let path = "plugin/DisplayLayerProgress/values";
+        let request = self
+            .server_request_builder(Method::GET, path)
+            .body(Body::empty())
+            .map_err(OctoprintError::from);
+        self.send(request).then(move |result| match result {
+            Ok(body) => parse_json::<DisplayLayerProgressResponse>(&format!("/{}", path), &body)
+                .map(|values| values.layer_progress()),
+            Err(OctoprintError::Http { status, .. }) if status == StatusCode::NOT_FOUND => Ok(None),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Loads the finished prints from the Print History plugin, or none if it isn't installed
+    pub fn load_print_history(
+        &mut self,
+    ) -> impl Future<Item = Option<PrintHistoryResponse>, Error = OctoprintError> {
+        let path = "plugin/printhistory/history";
+        let request = self
+            .server_request_builder(Method::GET, path)
+            .body(Body::empty())
+            .map_err(OctoprintError::from);
+        self.send(request).then(move |result| match result {
+            Ok(body) => parse_json(&format!("/{}", path), &body).map(Some),
+            Err(OctoprintError::Http { status, .. }) if status == StatusCode::NOT_FOUND => Ok(None),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Loads the Continuous Print plugin's queues, or none if it isn't installed
+    pub fn load_print_queue(
+        &mut self,
+    ) -> impl Future<Item = Option<ContinuousPrintState>, Error = OctoprintError> {
+        let path = "plugin/continuousprint/state/get";
+        let request = self
+            .server_request_builder(Method::GET, path)
+            .body(Body::empty())
+            .map_err(OctoprintError::from);
+        self.send(request).then(move |result| match result {
+            Ok(body) => parse_json(&format!("/{}", path), &body).map(Some),
+            Err(OctoprintError::Http { status, .. }) if status == StatusCode::NOT_FOUND => Ok(None),
+            Err(e) => Err(e),
+        })
+    }
+
+    /// Loads whether the PSU Control plugin has the printer powered, or none if it isn't installed
+    pub fn load_psu_state(&mut self) -> impl Future<Item = Option<bool>, Error = OctoprintError> {
+        let path = "plugin/psucontrol";
+        let request = self.json_request(Method::POST, path, &PsuCommand::GetState);
+        self.send(request)
+            .then(move |result| psu_state(&format!("/api/{}", path), result))
+    }
+
+    pub fn psu_on(&mut self) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command("plugin/psucontrol".to_string(), &PsuCommand::TurnOn)
+    }
+
+    pub fn psu_off(&mut self) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command("plugin/psucontrol".to_string(), &PsuCommand::TurnOff)
     }
 
     pub fn load_job(&mut self) -> impl Future<Item = JobResponse, Error = OctoprintError> {
@@ -187,4 +1178,463 @@ impl OctoprintClient {
     pub fn load_state(&mut self) -> impl Future<Item = StateResponse, Error = OctoprintError> {
         self.send_request("printer".to_string())
     }
+
+    pub fn load_connection(
+        &mut self,
+    ) -> impl Future<Item = ConnectionResponse, Error = OctoprintError> {
+        self.send_request("connection".to_string())
+    }
+
+    pub fn load_files(&mut self) -> impl Future<Item = FilesResponse, Error = OctoprintError> {
+        self.send_request("files/local?recursive=true".to_string())
+    }
+
+    pub fn load_sd_files(&mut self) -> impl Future<Item = FilesResponse, Error = OctoprintError> {
+        self.send_request("files/sdcard".to_string())
+    }
+
+    pub fn load_settings(
+        &mut self,
+    ) -> impl Future<Item = SettingsResponse, Error = OctoprintError> {
+        self.send_request("settings".to_string())
+    }
+
+    /// Loads the details of one file, including its analysis
+    pub fn load_file(
+        &mut self,
+        origin: &Origin,
+        path: &str,
+    ) -> impl Future<Item = FileInfo, Error = OctoprintError> {
+        self.send_request(format!("files/{}/{}", origin, encode_path(path)))
+    }
+
+    /// Selects a file and starts printing it
+    pub fn select_file(
+        &mut self,
+        origin: &Origin,
+        path: &str,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command(
+            format!("files/{}/{}", origin, encode_path(path)),
+            &FileCommand::Select { print: true },
+        )
+    }
+
+    pub fn pause_job(&mut self) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command(
+            "job".to_string(),
+            &JobCommand::Pause {
+                action: PauseAction::Pause,
+            },
+        )
+    }
+
+    pub fn resume_job(&mut self) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command(
+            "job".to_string(),
+            &JobCommand::Pause {
+                action: PauseAction::Resume,
+            },
+        )
+    }
+
+    pub fn cancel_job(&mut self) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command("job".to_string(), &JobCommand::Cancel)
+    }
+
+    /// Sets the target temperature of hotend `tool`, starting at 0
+    pub fn set_tool_temperature(
+        &mut self,
+        tool: usize,
+        target: f64,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        let mut targets = HashMap::new();
+        targets.insert(format!("tool{}", tool), target);
+        self.send_command("printer/tool".to_string(), &ToolCommand::Target { targets })
+    }
+
+    pub fn set_bed_temperature(
+        &mut self,
+        target: f64,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command("printer/bed".to_string(), &BedCommand::Target { target })
+    }
+
+    /// Moves the print head relative to its current position, in mm
+    pub fn jog(
+        &mut self,
+        x: f64,
+        y: f64,
+        z: f64,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command(
+            "printer/printhead".to_string(),
+            &PrintheadCommand::Jog { x, y, z },
+        )
+    }
+
+    pub fn home(
+        &mut self,
+        axes: Vec<PrinterAxis>,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command(
+            "printer/printhead".to_string(),
+            &PrintheadCommand::Home { axes },
+        )
+    }
+
+    /// Connects to the printer, with OctoPrint picking the port or baudrate when they are none
+    pub fn connect(
+        &mut self,
+        port: Option<String>,
+        baudrate: Option<u32>,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command(
+            "connection".to_string(),
+            &ConnectionCommand::Connect { port, baudrate },
+        )
+    }
+
+    pub fn disconnect(&mut self) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command("connection".to_string(), &ConnectionCommand::Disconnect)
+    }
+
+    /// Sends raw G-code lines to the printer
+    pub fn send_gcode(
+        &mut self,
+        commands: Vec<String>,
+    ) -> impl Future<Item = (), Error = OctoprintError> {
+        self.send_command("printer/command".to_string(), &GcodeCommands { commands })
+    }
+}
+
+/// Parses a json body, keeping the part around any error so schema changes can be tracked down
+pub fn parse_json<R: DeserializeOwned>(source: &str, body: &[u8]) -> Result<R, OctoprintError> {
+    serde_json::from_slice(body).map_err(|err| {
+        let body = String::from_utf8_lossy(body);
+        let line = body.lines().nth(err.line().saturating_sub(1)).unwrap_or("");
+        // serde's column counts bytes, so cut the line on the char boundaries around it
+        let mut start = err.column().saturating_sub(40).min(line.len());
+        while !line.is_char_boundary(start) {
+            start -= 1;
+        }
+        let mut end = (start + 80).min(line.len());
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        OctoprintError::Parse {
+            source: source.to_string(),
+            context: line[start..end].to_string(),
+            err,
+        }
+    })
+}
+
+/// The power state from a PSU Control `GetState` response
+///
+/// A 404 means the plugin isn't installed. `GetState` is a POST, which the plugin forbids for a
+/// read-only API key, so a 403 is treated the same rather than failing every poll.
+fn psu_state(
+    source: &str,
+    result: Result<Chunk, OctoprintError>,
+) -> Result<Option<bool>, OctoprintError> {
+    match result {
+        Ok(body) => {
+            parse_json::<PsuStateResponse>(source, &body).map(|state| Some(state.is_psu_on))
+        }
+        Err(OctoprintError::Http { status, .. }) if status == StatusCode::NOT_FOUND => Ok(None),
+        Err(OctoprintError::Forbidden { .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Normalizes the server URL so api paths can be appended to it, keeping any path prefix a
+/// reverse proxy serves OctoPrint under, so `https://home.example/octoprint/` becomes
+/// `https://home.example/octoprint`
+fn base_url(url: &str) -> String {
+    url.trim().trim_end_matches('/').to_string()
+}
+
+/// Percent-encodes each part of a file path, so names like `my model (v2).gcode` stay one
+/// path segment while the folders between them are kept
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(|part| utf8_percent_encode(part, PATH_SEGMENT_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// The error for a 403, with the `error` text from OctoPrint's json body. Older versions send an
+/// html page, which says nothing more than the status.
+fn forbidden(path: String, body: &[u8]) -> OctoprintError {
+    OctoprintError::Forbidden {
+        path,
+        reason: serde_json::from_slice::<ErrorResponse>(body)
+            .ok()
+            .map(|response| error_body(response.error.as_bytes())),
+    }
+}
+
+/// Shortens an error response body so it fits in an error message
+fn error_body(body: &[u8]) -> String {
+    String::from_utf8_lossy(body).chars().take(200).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The URI a client would request for an api path
+    fn api_uri(url: &str, path: &str) -> String {
+        let client = OctoprintClient::new(
+            url.to_string(),
+            String::new(),
+            AuthScheme::ApiKey,
+            Duration::from_secs(1),
+            1024,
+        )
+        .unwrap();
+        client
+            .request_builder(Method::GET, path)
+            .body(Body::empty())
+            .unwrap()
+            .uri()
+            .to_string()
+    }
+
+    #[test]
+    fn base_url_trims_trailing_slashes() {
+        assert_eq!(base_url("http://octopi.local/"), "http://octopi.local");
+        assert_eq!(base_url(" http://octopi.local// "), "http://octopi.local");
+        assert_eq!(
+            api_uri("http://octopi.local:5000/", "job"),
+            "http://octopi.local:5000/api/job"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_requests_use_placeholder_host() {
+        assert_eq!(
+            api_uri("unix:///run/octoprint.sock", "job"),
+            "http://localhost/api/job"
+        );
+
+        let client = OctoprintClient::new(
+            "unix:///run/octoprint.sock".to_string(),
+            String::new(),
+            AuthScheme::ApiKey,
+            Duration::from_secs(1),
+            1024,
+        )
+        .unwrap();
+        assert!(client.is_unix());
+        assert!(client.absolute_url("/webcam/?action=stream").is_err());
+        assert_eq!(
+            client.absolute_url("http://camera.local/stream").unwrap(),
+            "http://camera.local/stream"
+        );
+    }
+
+    #[test]
+    fn base_url_keeps_subpath() {
+        assert_eq!(
+            base_url("https://home.example/octoprint/"),
+            "https://home.example/octoprint"
+        );
+        assert_eq!(
+            api_uri("https://home.example/octoprint/", "printer"),
+            "https://home.example/octoprint/api/printer"
+        );
+        assert_eq!(
+            api_uri("https://home.example/octoprint", "printer"),
+            "https://home.example/octoprint/api/printer"
+        );
+    }
+
+    #[test]
+    fn encode_path_encodes_each_part() {
+        assert_eq!(
+            encode_path("my model (v2).gcode"),
+            "my%20model%20(v2).gcode"
+        );
+        assert_eq!(
+            encode_path("prints/part #3.gcode"),
+            "prints/part%20%233.gcode"
+        );
+        assert_eq!(
+            encode_path("prints/café/ベンチ.gcode"),
+            "prints/caf%C3%A9/%E3%83%99%E3%83%B3%E3%83%81.gcode"
+        );
+    }
+
+    #[test]
+    fn psu_state_without_plugin_is_none() {
+        let http = |status| {
+            Err(OctoprintError::Http {
+                status,
+                body: String::new(),
+            })
+        };
+        assert_eq!(psu_state("", http(StatusCode::NOT_FOUND)).unwrap(), None);
+        let forbidden = Err(forbidden("/api/plugin/psucontrol".to_string(), b""));
+        assert_eq!(psu_state("", forbidden).unwrap(), None);
+        assert!(psu_state("", http(StatusCode::INTERNAL_SERVER_ERROR)).is_err());
+
+        let on = Ok(Chunk::from(r#"{"isPSUOn": true}"#));
+        assert_eq!(psu_state("", on).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn forbidden_keeps_the_reason() {
+        let json =
+            br#"{"error": "You don't have the permission to access the requested resource."}"#;
+        assert_eq!(
+            forbidden("/api/job".to_string(), json).to_string(),
+            "Invalid key or insufficient permissions for /api/job: You don't have the \
+             permission to access the requested resource."
+        );
+        assert_eq!(
+            forbidden("/api/job".to_string(), b"<html>403 Forbidden</html>").to_string(),
+            "Invalid key or insufficient permissions for /api/job"
+        );
+    }
+
+    #[test]
+    fn empty_job_deserializes() {
+        for payload in &[
+            "{}",
+            r#"{"job": null, "progress": null}"#,
+            r#"{
+                "job": {
+                    "file": {"name": null, "origin": null, "size": null, "date": null},
+                    "estimatedPrintTime": null,
+                    "filament": null
+                },
+                "progress": {"completion": null, "filepos": null, "printTime": null},
+                "state": "Operational"
+            }"#,
+        ] {
+            let job: JobResponse = serde_json::from_str(payload).unwrap();
+            assert!(job.job.file.name.is_none());
+            assert!(job.progress.completion.is_none());
+        }
+    }
+
+    #[test]
+    fn unknown_origin_deserializes() {
+        let files: FilesResponse = serde_json::from_str(
+            r#"{
+                "files": [
+                    {"name": "a.gcode", "path": "a.gcode", "type": "machinecode", "origin": "local"},
+                    {"name": "b.gcode", "path": "b.gcode", "type": "machinecode", "origin": "usb"}
+                ],
+                "free": 1024
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(files.files[0].origin, Origin::Local);
+        assert_eq!(files.files[1].origin, Origin::Other("usb".to_string()));
+
+        let job: JobResponse =
+            serde_json::from_str(r#"{"job": {"file": {"name": "b.gcode", "origin": "usb"}}}"#)
+                .unwrap();
+        assert_eq!(job.job.file.origin, Some(Origin::Other("usb".to_string())));
+    }
+
+    #[test]
+    fn origin_serializes_as_sent() {
+        let origins = [
+            Origin::Local,
+            Origin::SdCard,
+            Origin::Other("usb".to_string()),
+        ];
+        assert_eq!(
+            serde_json::to_string(&origins).unwrap(),
+            r#"["local","sdcard","usb"]"#
+        );
+    }
+
+    /// Responses from each OctoPrint version, which name and leave out fields differently
+    const JOB_FIXTURES: &[(&str, &str)] = &[
+        ("1.3", include_str!("../tests/fixtures/job-1.3.json")),
+        ("1.5", include_str!("../tests/fixtures/job-1.5.json")),
+        ("1.9", include_str!("../tests/fixtures/job-1.9.json")),
+    ];
+
+    const PRINTER_FIXTURES: &[(&str, &str)] = &[
+        ("1.3", include_str!("../tests/fixtures/printer-1.3.json")),
+        ("1.5", include_str!("../tests/fixtures/printer-1.5.json")),
+        ("1.9", include_str!("../tests/fixtures/printer-1.9.json")),
+    ];
+
+    #[test]
+    fn job_fixtures_deserialize() {
+        for (version, fixture) in JOB_FIXTURES {
+            let job: JobResponse = serde_json::from_str(fixture)
+                .unwrap_or_else(|e| panic!("OctoPrint {} job: {}", version, e));
+            assert_eq!(job.job.file.origin, Some(Origin::Local), "{}", version);
+            assert!(job.job.file.name.is_some(), "{}", version);
+            assert!(job.job.estimated_print_time.is_some(), "{}", version);
+            assert!(job.progress.completion.is_some(), "{}", version);
+        }
+    }
+
+    #[test]
+    fn printer_fixtures_deserialize() {
+        for (version, fixture) in PRINTER_FIXTURES {
+            let state: StateResponse = serde_json::from_str(fixture)
+                .unwrap_or_else(|e| panic!("OctoPrint {} printer: {}", version, e));
+            let temperature = state.temperature.expect(version);
+            assert!(temperature.tool0.is_some(), "{}", version);
+            assert!(temperature.bed.is_some(), "{}", version);
+            assert!(state.state.expect(version).flags.operational, "{}", version);
+        }
+    }
+
+    #[test]
+    fn chamber_deserializes() {
+        let state: StateResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/printer-chamber.json")).unwrap();
+        let chamber = state.temperature.unwrap().chamber.unwrap();
+        assert_eq!(chamber.actual, 41.3);
+        assert_eq!(chamber.target, 45.0);
+
+        // Open frame printers leave it out
+        for (version, fixture) in PRINTER_FIXTURES {
+            let state: StateResponse = serde_json::from_str(fixture).unwrap();
+            assert!(state.temperature.unwrap().chamber.is_none(), "{}", version);
+        }
+    }
+
+    #[test]
+    fn missing_flags_are_false() {
+        let state: StateResponse = serde_json::from_str(PRINTER_FIXTURES[0].1).unwrap();
+        let flags = state.state.unwrap().flags;
+        assert!(!flags.pausing);
+        assert!(!flags.cancelling);
+    }
+
+    #[test]
+    fn null_target_is_zero() {
+        let state: StateResponse = serde_json::from_str(PRINTER_FIXTURES[0].1).unwrap();
+        assert_eq!(state.temperature.unwrap().tool1.unwrap().target, 0.0);
+    }
+
+    #[test]
+    fn parse_context_around_multibyte_text() {
+        let body = format!(r#"{{"name": "{}", "size": x}}"#, "\u{e9}".repeat(60));
+        match parse_json::<serde_json::Value>("/api/files", body.as_bytes()) {
+            Err(OctoprintError::Parse { context, .. }) => {
+                assert!(context.contains(r#"", "size": x"#), "{}", context);
+                assert!(context.len() <= 80);
+            }
+            _ => panic!("expected a parse error"),
+        }
+    }
 }
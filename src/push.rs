@@ -0,0 +1,203 @@
+use futures::future::Either;
+use futures::stream;
+use futures::Future;
+use futures::IntoFuture;
+use futures::Sink;
+use futures::Stream;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use websocket::ClientBuilder;
+use websocket::OwnedMessage;
+
+use crate::octoprint::*;
+use crate::ui::UiError;
+use crate::ui::UiEvent;
+
+/// The printer state OctoPrint pushes as it changes
+#[derive(Deserialize, Debug, Clone)]
+struct CurrentData {
+    state: PrinterState,
+    #[serde(default, deserialize_with = "null_as_default")]
+    job: Job,
+    #[serde(default, deserialize_with = "null_as_default")]
+    progress: Progress,
+    #[serde(rename = "currentZ")]
+    current_z: Option<f64>,
+    /// Only the readings since the last message, so this is often empty
+    #[serde(default)]
+    temps: Vec<HistoricTemperatureData>,
+    /// The lines sent to and received from the printer since the last message
+    #[serde(default)]
+    logs: Vec<String>,
+}
+
+/// The fan speed and speed and flow multipliers, in percent
+///
+/// OctoPrint doesn't report these, so they are tracked from the G-code it sends to the printer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tuning {
+    pub fan: Option<f64>,
+    pub speed: Option<f64>,
+    pub flow: Option<f64>,
+}
+
+impl Tuning {
+    /// Updates the values from a terminal log line like `Send: N12 M106 S255*93`
+    fn update(&mut self, line: &str) {
+        let line = match line.trim().splitn(2, "Send: ").nth(1) {
+            Some(line) => line,
+            None => return,
+        };
+        // Drop the checksum and line number OctoPrint adds while printing
+        let line = line.split('*').next().unwrap_or("");
+        let mut words = line
+            .split_whitespace()
+            .skip_while(|word| word.starts_with('N'));
+        let command = words.next().unwrap_or("");
+
+        let mut value = None;
+        let mut index = 0;
+        for word in words {
+            if word.starts_with('S') {
+                value = word[1..].parse::<f64>().ok();
+            } else if word.starts_with('P') {
+                index = word[1..].parse().unwrap_or(0);
+            }
+        }
+
+        match command {
+            // Only the first fan is shown
+            "M106" if index == 0 => self.fan = Some(value.unwrap_or(255.0) / 255.0 * 100.0),
+            "M107" if index == 0 => self.fan = Some(0.0),
+            "M220" => self.speed = value.or(self.speed),
+            "M221" => self.flow = value.or(self.flow),
+            _ => {}
+        }
+    }
+}
+
+/// A message from the push socket, of which only the printer state is used
+#[derive(Deserialize, Debug, Clone)]
+struct PushMessage {
+    current: Option<CurrentData>,
+    history: Option<CurrentData>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct AuthMessage {
+    auth: String,
+}
+
+/// The push socket lives at `/sockjs/websocket` on the same host as the api
+fn socket_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    if url.starts_with("https://") {
+        format!("wss://{}/sockjs/websocket", &url["https://".len()..])
+    } else if url.starts_with("http://") {
+        format!("ws://{}/sockjs/websocket", &url["http://".len()..])
+    } else {
+        format!("{}/sockjs/websocket", url)
+    }
+}
+
+/// Connects to OctoPrint's push socket, resolving to a stream of the updates it sends
+pub fn connect(
+    octoprint: &mut OctoprintClient,
+) -> impl Future<Item = impl Stream<Item = UiEvent, Error = OctoprintError>, Error = OctoprintError>
+{
+    let url = socket_url(octoprint.url());
+
+    octoprint
+        .login()
+        .and_then(move |login| {
+            let builder = match ClientBuilder::new(&url) {
+                Ok(builder) => builder,
+                Err(e) => return Either::A(Err(OctoprintError::from(e)).into_future()),
+            };
+
+            // The socket only sends printer updates once it knows who we are
+            let auth = AuthMessage {
+                auth: format!("{}:{}", login.name, login.session),
+            };
+            let auth = serde_json::to_string(&auth).expect("Could not serialize auth message");
+
+            Either::B(
+                builder
+                    .async_connect(None)
+                    .from_err::<OctoprintError>()
+                    .and_then(|(client, _)| client.send(OwnedMessage::Text(auth)).from_err()),
+            )
+        })
+        .map(|client| {
+            let mut temperature = None;
+            let mut tuning = Tuning::default();
+            client
+                .from_err::<OctoprintError>()
+                .map(move |message| {
+                    let events = match message {
+                        OwnedMessage::Text(text) => {
+                            match parse_json("the push socket", text.as_bytes()) {
+                                Ok(message) => {
+                                    message_events(message, &mut temperature, &mut tuning)
+                                }
+                                Err(e) => vec![UiEvent::Error(UiError::from(e))],
+                            }
+                        }
+                        _ => Vec::new(),
+                    };
+                    stream::iter_ok(events)
+                })
+                .flatten()
+        })
+}
+
+/// Turns a pushed message into the same events the pollers send
+fn message_events(
+    message: PushMessage,
+    temperature: &mut Option<TemperatureState>,
+    tuning: &mut Tuning,
+) -> Vec<UiEvent> {
+    let current = match message.current.or(message.history) {
+        Some(current) => current,
+        None => return Vec::new(),
+    };
+
+    let mut events = Vec::new();
+
+    let previous_tuning = tuning.clone();
+    for line in &current.logs {
+        tuning.update(line);
+    }
+    if *tuning != previous_tuning {
+        events.push(UiEvent::TuningUpdate(tuning.clone()));
+    }
+
+    // Keep showing the last temperatures when a message has no new readings
+    if let Some(temps) = current.temps.last() {
+        *temperature = Some(TemperatureState {
+            tool0: temps.tool0.clone(),
+            tool1: temps.tool1.clone(),
+            tool2: temps.tool2.clone(),
+            bed: temps.bed.clone(),
+            chamber: temps.chamber.clone(),
+            history: None,
+        });
+    }
+
+    events.push(UiEvent::JobUpdate(JobResponse {
+        job: current.job,
+        progress: current.progress,
+    }));
+    events.push(UiEvent::StateUpdate(StateResponse {
+        temperature: temperature.clone(),
+        sd: Some(SdState {
+            ready: current.state.flags.sd_ready,
+        }),
+        state: Some(current.state),
+        current_z: current.current_z,
+    }));
+
+    events
+}
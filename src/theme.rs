@@ -0,0 +1,234 @@
+use tui::style::{Color, Modifier, Style};
+
+use crate::config::ThemeColors;
+use crate::config::ThemeConfig;
+
+/// How a temperature compares to its target, which picks the color it is drawn in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Heat {
+    /// The heater is off
+    Off,
+    Heating,
+    NearTarget,
+    AtTarget,
+    AboveTarget,
+}
+
+/// The styles every widget is drawn with, so colors can be changed or turned off in one place
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    /// Whether to draw any colors or modifiers at all
+    color: bool,
+    /// Whether to draw everything in dark gray while the ui is idle
+    dimmed: bool,
+    foreground: Color,
+    background: Color,
+    status: Color,
+    error: Color,
+    gauge: Color,
+    heating: Color,
+    near_target: Color,
+    at_target: Color,
+    above_target: Color,
+}
+
+/// The names of the built in themes, for error messages
+const THEME_NAMES: &str = "default, solarized, light";
+
+impl Theme {
+    /// White text on black, the colors used when no theme is set
+    pub fn default_theme() -> Theme {
+        Theme {
+            color: true,
+            dimmed: false,
+            foreground: Color::White,
+            background: Color::Black,
+            status: Color::White,
+            error: Color::Red,
+            gauge: Color::White,
+            heating: Color::Red,
+            near_target: Color::Yellow,
+            at_target: Color::Green,
+            above_target: Color::Blue,
+        }
+    }
+
+    /// One of the built in themes by name
+    pub fn named(name: &str) -> Option<Theme> {
+        let theme = match name {
+            "default" => Theme::default_theme(),
+            // The dark variant of https://ethanschoonover.com/solarized
+            "solarized" => Theme {
+                foreground: Color::Rgb(0x83, 0x94, 0x96),
+                background: Color::Rgb(0x00, 0x2b, 0x36),
+                status: Color::Rgb(0x93, 0xa1, 0xa1),
+                error: Color::Rgb(0xdc, 0x32, 0x2f),
+                gauge: Color::Rgb(0x26, 0x8b, 0xd2),
+                heating: Color::Rgb(0xcb, 0x4b, 0x16),
+                near_target: Color::Rgb(0xb5, 0x89, 0x00),
+                at_target: Color::Rgb(0x85, 0x99, 0x00),
+                above_target: Color::Rgb(0x2a, 0xa1, 0x98),
+                ..Theme::default_theme()
+            },
+            "light" => Theme {
+                foreground: Color::Black,
+                background: Color::White,
+                status: Color::Black,
+                error: Color::Red,
+                gauge: Color::Blue,
+                heating: Color::Red,
+                near_target: Color::Magenta,
+                at_target: Color::Green,
+                above_target: Color::Blue,
+                ..Theme::default_theme()
+            },
+            _ => return None,
+        };
+        Some(theme)
+    }
+
+    /// The theme from the config file, or the default theme if there is none
+    pub fn load(config: Option<&ThemeConfig>, color: bool) -> Result<Theme, String> {
+        let (name, custom) = match config {
+            Some(ThemeConfig::Name(name)) => (Some(name.as_str()), None),
+            Some(ThemeConfig::Table { name, custom }) => {
+                (name.as_ref().map(|n| n.as_str()), Some(custom))
+            }
+            None => (None, None),
+        };
+
+        let mut theme = match name {
+            Some(name) => Theme::named(name)
+                .ok_or_else(|| format!("Unknown theme '{}', use one of {}", name, THEME_NAMES))?,
+            None => Theme::default_theme(),
+        };
+        if let Some(custom) = custom {
+            theme.customize(custom)?;
+        }
+        theme.color = color;
+        Ok(theme)
+    }
+
+    /// Replaces the colors that are set in a `[theme.custom]` table
+    fn customize(&mut self, custom: &ThemeColors) -> Result<(), String> {
+        let colors = vec![
+            (&custom.foreground, &mut self.foreground),
+            (&custom.background, &mut self.background),
+            (&custom.status, &mut self.status),
+            (&custom.error, &mut self.error),
+            (&custom.gauge, &mut self.gauge),
+            (&custom.heating, &mut self.heating),
+            (&custom.near_target, &mut self.near_target),
+            (&custom.at_target, &mut self.at_target),
+            (&custom.above_target, &mut self.above_target),
+        ];
+        for (name, color) in colors {
+            if let Some(name) = name {
+                *color = parse_color(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The same theme with everything in dark gray on black if `dimmed` is set, so a screen
+    /// that is always on doesn't burn in while nothing is happening
+    pub fn dimmed(self, dimmed: bool) -> Theme {
+        Theme { dimmed, ..self }
+    }
+
+    /// The style everything is drawn in unless it stands out
+    pub fn base(self) -> Style {
+        if !self.color {
+            Style::default()
+        } else if self.dimmed {
+            Style::default().fg(Color::DarkGray).bg(Color::Black)
+        } else {
+            Style::default().fg(self.foreground).bg(self.background)
+        }
+    }
+
+    /// The base style with another text color
+    pub fn fg(self, color: Color) -> Style {
+        if self.color && !self.dimmed {
+            self.base().fg(color)
+        } else {
+            self.base()
+        }
+    }
+
+    /// Dark text on a colored background, like the printer flag chips
+    pub fn chip(self, color: Color) -> Style {
+        if self.color && !self.dimmed {
+            self.base().fg(Color::Black).bg(color)
+        } else {
+            self.base()
+        }
+    }
+
+    /// Adds a modifier like bold to a style from the theme
+    pub fn modifier(self, style: Style, modifier: Modifier) -> Style {
+        if self.color {
+            style.modifier(modifier)
+        } else {
+            style
+        }
+    }
+
+    /// The printer status text
+    pub fn status(self) -> Style {
+        self.fg(self.status)
+    }
+
+    /// Error messages
+    pub fn error(self) -> Style {
+        self.fg(self.error)
+    }
+
+    /// The progress bar, which is filled in the text color
+    pub fn gauge(self) -> Style {
+        self.fg(self.gauge)
+    }
+
+    /// A temperature reading
+    pub fn temperature(self, heat: Heat) -> Style {
+        match heat {
+            Heat::Off => self.base(),
+            Heat::Heating => self.fg(self.heating),
+            Heat::NearTarget => self.fg(self.near_target),
+            Heat::AtTarget => self.fg(self.at_target),
+            Heat::AboveTarget => self.fg(self.above_target),
+        }
+    }
+}
+
+/// Parses a color name like `lightred`, or a hex color like `#268bd2`
+fn parse_color(name: &str) -> Result<Color, String> {
+    let color = match name.to_lowercase().as_str() {
+        "reset" => Color::Reset,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        hex if hex.starts_with('#') && hex.len() == 7 => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+            match (channel(1), channel(3), channel(5)) {
+                (Ok(r), Ok(g), Ok(b)) => Color::Rgb(r, g, b),
+                _ => return Err(format!("Invalid hex color '{}'", name)),
+            }
+        }
+        _ => return Err(format!("Unknown color '{}'", name)),
+    };
+    Ok(color)
+}
@@ -1,18 +1,94 @@
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::process;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use chrono::DateTime;
+use chrono::Local;
+use futures::sync::mpsc;
 use futures::Future;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
 
 use tui::backend::Backend;
-use tui::layout::{Alignment, Constraint, Direction, Layout};
+use tui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
-use tui::widgets::{Block, Borders, Gauge, Paragraph, Row, Table, Text, Widget};
+use tui::widgets::{
+    Axis, Block, Borders, Chart, Dataset, Gauge, Marker, Paragraph, Row, SelectableList, Table,
+    Tabs, Text, Widget,
+};
+use tui::Frame;
 use tui::Terminal;
 
+use termion::event::Key;
+
+use crate::command::Command;
+use crate::config::Preset;
+use crate::config::SavedState;
+use crate::metrics::Metrics;
+use crate::metrics::SharedMetrics;
+use crate::octoprint::ConnectionOptions;
+use crate::octoprint::ConnectionResponse;
+use crate::octoprint::ConnectionState;
+use crate::octoprint::ContinuousPrintState;
+use crate::octoprint::FileInfo;
+use crate::octoprint::FileType;
+use crate::octoprint::FilesResponse;
+use crate::octoprint::HistorySummary;
 use crate::octoprint::JobResponse;
+use crate::octoprint::LayerProgress;
 use crate::octoprint::OctoprintError;
+use crate::octoprint::Origin;
+use crate::octoprint::PrinterAxis;
+use crate::octoprint::PrinterFlags;
+use crate::octoprint::QueuedJob;
 use crate::octoprint::StateResponse;
+use crate::octoprint::TemperatureData;
+use crate::push::Tuning;
+use crate::theme::Heat;
+use crate::theme::Theme;
+use crate::webcam::Snapshot;
+use crate::webcam::SnapshotView;
 
 pub enum UiEvent {
     JobUpdate(JobResponse),
     StateUpdate(StateResponse),
+    ConnectionUpdate(ConnectionResponse),
+    FilesUpdate(FilesResponse),
+    SdFilesUpdate(FilesResponse),
+    Key(Key),
+    TuningUpdate(Tuning),
+    /// The layer counts, or none without the DisplayLayerProgress plugin
+    LayerUpdate(Option<LayerProgress>),
+    /// Whether the printer is powered, or none without the PSU Control plugin
+    PsuUpdate(Option<bool>),
+    /// The print queues, or none without the Continuous Print plugin
+    QueueUpdate(Option<ContinuousPrintState>),
+    /// What the print history adds up to, or none without the Print History plugin
+    HistoryUpdate(Option<HistorySummary>),
+    /// The job and printer state were both loaded by a poll, which is counted in the poll stats
+    Polled,
+    /// Whether the push socket is connected, or has dropped and left updates to polling
+    PushActive(bool),
+    ProfileSwitched(usize),
+    /// Rows of Z offsets from the bed mesh, or none if there is no mesh
+    BedMeshUpdate(Option<Vec<Vec<f64>>>),
+    /// The details of the selected file
+    FileDetailUpdate(FileInfo),
+    /// The latest webcam snapshot, or none if there is no webcam
+    #[cfg_attr(not(feature = "webcam"), allow(dead_code))]
+    WebcamUpdate(Option<Snapshot>),
+    Resize,
+    /// Redraws once a second so the clock and update age keep moving
+    Tick,
+    Error(UiError),
 }
 
 impl From<JobResponse> for UiEvent {
@@ -25,6 +101,28 @@ impl From<JobResponse> for UiEvent {
 pub enum UiError {
     Timer(tokio_timer::Error),
     Octoprint(OctoprintError),
+    /// A failed poll of the job and printer state, which is counted in the poll stats
+    Poll(OctoprintError),
+    Profile(String),
+    Webcam(String),
+    /// No updates have arrived for this long
+    Stalled(Duration),
+}
+
+impl fmt::Display for UiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UiError::Timer(err) => write!(f, "Timer error: {}", err),
+            UiError::Octoprint(err) | UiError::Poll(err) => write!(f, "{}", err),
+            UiError::Profile(err) => write!(f, "Could not switch profile: {}", err),
+            UiError::Webcam(err) => write!(f, "{}", err),
+            UiError::Stalled(age) => write!(
+                f,
+                "No updates for {} seconds, restart to reconnect",
+                age.as_secs()
+            ),
+        }
+    }
 }
 
 impl From<tokio_timer::Error> for UiError {
@@ -39,314 +137,3929 @@ impl From<OctoprintError> for UiError {
     }
 }
 
-#[derive(Clone)]
-struct UiState {
-    progress: f64,
-    filename: Option<String>,
-    status: Option<String>,
-    print_time: Option<f64>,
-    estimated_time: Option<f64>,
-    remaining_time: Option<f64>,
-    hotend_temp: Option<f64>,
-    hotend_target: Option<f64>,
-    bed_temp: Option<f64>,
-    bed_target: Option<f64>,
+/// The jog step sizes that + and - move between, in mm
+const JOG_STEPS: [f64; 4] = [0.1, 1.0, 10.0, 100.0];
+
+/// Settings for the ui from the command line and config file
+pub struct UiOptions {
+    pub jog_step: f64,
+    /// How far each babystep moves Z, in mm
+    pub babystep: f64,
+    pub units: TemperatureUnit,
+    /// How often updates are expected while printing
+    pub poll_interval: Duration,
+    /// How often updates are expected while not printing
+    pub idle_poll_interval: Duration,
+    /// A shell command to run when a print completes
+    pub notify_command: Option<String>,
+    pub hooks: Hooks,
+    /// How much filament was on the spool when the print started, in m
+    pub spool_length: Option<f64>,
+    /// Whether to show hotends other than the first when they are off and reading 0
+    pub show_inactive_tools: bool,
+    /// The names of the printer profiles in the config file
+    pub profiles: Vec<String>,
+    /// The profile in use, if any
+    pub profile: Option<usize>,
+    pub presets: Vec<Preset>,
+    pub theme: Theme,
+    /// The view to start on
+    pub view: View,
+    /// Whether webcam snapshots are being loaded for the preview
+    pub webcam: bool,
+    pub sections: Sections,
+    /// How long without a key press or a print before the ui dims, if it should
+    pub idle_dim: Option<Duration>,
+    /// Whether to always draw the overview in a few lines, as on a short terminal
+    pub compact: bool,
+    /// Where to keep the readings for --metrics-addr, if it was given
+    pub metrics: Option<SharedMetrics>,
+    pub temperature_format: TemperatureFormat,
 }
 
-pub struct Ui<B: Backend> {
-    terminal: Terminal<B>,
-    state: UiState,
+/// Which optional parts of the ui to draw, so printers without a heated bed for example don't
+/// show an empty reading
+#[derive(Debug, Clone, Copy)]
+pub struct Sections {
+    pub bed: bool,
+    pub filament: bool,
+    pub estimated: bool,
+    pub tuning: bool,
 }
 
-impl<B: Backend> Ui<B> {
-    pub fn new(mut terminal: Terminal<B>) -> Ui<B> {
-        terminal.clear().expect("Could not clear terminal");
-        terminal.hide_cursor().expect("Could not hide cursor");
+/// How temperature readings are written, since two decimals is more than a thermistor can tell
+/// and some people write a comma for the decimal point
+#[derive(Debug, Clone, Copy)]
+pub struct TemperatureFormat {
+    /// Digits after the decimal separator
+    pub precision: usize,
+    pub decimal_separator: char,
+}
 
-        let state = UiState {
-            progress: 0.0,
-            filename: None,
-            status: None,
-            print_time: None,
-            estimated_time: None,
-            remaining_time: None,
-            hotend_temp: None,
-            hotend_target: None,
-            bed_temp: None,
-            bed_target: None,
-        };
+impl TemperatureFormat {
+    /// Writes a reading like `210.4`, or `210,4` with a comma separator
+    fn reading(self, temperature: f64) -> String {
+        let text = format!("{:.*}", self.precision, temperature);
+        if self.decimal_separator == '.' {
+            text
+        } else {
+            text.replace('.', &self.decimal_separator.to_string())
+        }
+    }
+}
+
+/// Shell commands to run when the printer changes state, from the config file
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    pub on_pause: Option<String>,
+    pub on_resume: Option<String>,
+    pub on_error: Option<String>,
+    pub on_complete: Option<String>,
+}
 
-        Ui { terminal, state }
+impl Hooks {
+    fn command(&self, kind: EventKind) -> Option<&String> {
+        match kind {
+            EventKind::Paused => self.on_pause.as_ref(),
+            EventKind::Resumed => self.on_resume.as_ref(),
+            EventKind::Error => self.on_error.as_ref(),
+            EventKind::Finished => self.on_complete.as_ref(),
+            _ => None,
+        }
     }
+}
 
-    pub fn draw(&mut self, event: UiEvent) {
-        match event {
-            UiEvent::JobUpdate(job) => {
-                self.state.progress = job.progress.completion.unwrap_or(0.0);
-                self.state.filename = job.job.file.name;
-                self.state.print_time = job.progress.print_time;
-                self.state.estimated_time =
-                    job.job.last_print_time.or(job.job.estimated_print_time);
-                self.state.remaining_time = job.progress.print_time_left;
-            }
-            UiEvent::StateUpdate(state) => {
-                self.state.status = state.state.map(|s| s.text);
-                self.state.hotend_temp = state
-                    .temperature
-                    .clone()
-                    .and_then(|t| t.tool0)
-                    .map(|t| t.actual);
-                self.state.hotend_target = state
-                    .temperature
-                    .clone()
-                    .and_then(|t| t.tool0)
-                    .map(|t| t.target);
-                self.state.bed_temp = state
-                    .temperature
-                    .clone()
-                    .and_then(|t| t.bed)
-                    .map(|t| t.actual);
-                self.state.bed_target = state
-                    .temperature
-                    .clone()
-                    .and_then(|t| t.bed)
-                    .map(|t| t.target);
-            }
-        }
-
-        let state = self.state.clone();
+/// The units temperatures are shown and entered in
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
 
-        self.terminal
-            .draw(|mut f| {
-                let size = f.size();
+impl TemperatureUnit {
+    /// Converts a temperature from OctoPrint, which is always in celsius
+    fn from_celsius(self, celsius: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
 
-                let style = Style::default().fg(Color::White).bg(Color::Black);
+    /// Converts a temperature in these units back to celsius for OctoPrint
+    fn to_celsius(self, temperature: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => temperature,
+            TemperatureUnit::Fahrenheit => (temperature - 32.0) * 5.0 / 9.0,
+        }
+    }
 
-                Block::default().style(style).render(&mut f, size);
+    /// Converts a temperature difference, like an offset, which has no zero point to shift
+    fn difference_from_celsius(self, difference: f64) -> f64 {
+        match self {
+            TemperatureUnit::Celsius => difference,
+            TemperatureUnit::Fahrenheit => difference * 9.0 / 5.0,
+        }
+    }
 
-                let title = state.filename.unwrap_or("No File".to_string());
+    fn symbol(self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "°C",
+            TemperatureUnit::Fahrenheit => "°F",
+        }
+    }
 
-                let chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .margin(0)
-                    .constraints(
-                        [
-                            Constraint::Length(1),
-                            Constraint::Length(1), // Status
-                            Constraint::Length(1), // Filename
-                            Constraint::Length(5),
-                            Constraint::Length(2), // Temperatures
-                            Constraint::Min(5),
-                            Constraint::Length(2), // Times
-                            Constraint::Length(1),
-                            Constraint::Length(1), // Progress
-                            Constraint::Length(1),
-                        ]
-                        .as_ref(),
-                    )
-                    .split(f.size());
+    fn toggled(self) -> TemperatureUnit {
+        match self {
+            TemperatureUnit::Celsius => TemperatureUnit::Fahrenheit,
+            TemperatureUnit::Fahrenheit => TemperatureUnit::Celsius,
+        }
+    }
+}
 
-                let status_chunk = chunks[1];
-                let filename_chunk = chunks[2];
-                let temperatures_chunk = chunks[4];
-                let times_chunk = chunks[6];
-                let progress_chunk = chunks[8];
+impl FromStr for TemperatureUnit {
+    type Err = String;
 
-                Paragraph::new(
-                    [Text::Styled(
-                        state.status.unwrap_or("No Status".to_string()).into(),
-                        style,
-                    )]
-                    .into_iter(),
-                )
-                .style(style)
-                .alignment(Alignment::Center)
-                .render(&mut f, status_chunk);
+    fn from_str(s: &str) -> Result<TemperatureUnit, String> {
+        match s {
+            "celsius" | "c" => Ok(TemperatureUnit::Celsius),
+            "fahrenheit" | "f" => Ok(TemperatureUnit::Fahrenheit),
+            _ => Err(format!("Unknown units '{}', use celsius or fahrenheit", s)),
+        }
+    }
+}
 
-                Paragraph::new([Text::Styled(title.into(), style)].iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, filename_chunk);
+/// How long after the first press of cancel, or of quit with a pause queued, the second press
+/// goes ahead
+const CONFIRM_TIME: Duration = Duration::from_secs(3);
 
-                let temperature_chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .margin(0)
-                    .constraints([Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)].as_ref())
-                    .split(temperatures_chunk);
+/// How long Z has to stay at or above a queued pause height before pausing, so the short lift of
+/// a z-hop doesn't pause a layer early
+const PAUSE_AT_Z_HOLD: Duration = Duration::from_secs(2);
 
-                let hotend_chucks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .margin(0)
-                    .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
-                    .split(temperature_chunks[0]);
+/// The screens that Tab switches between, each focused on one part of the printer
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum View {
+    Overview,
+    Files,
+    Temps,
+    Console,
+}
 
-                Paragraph::new([Text::Styled("Hotend".into(), style)].into_iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, hotend_chucks[0]);
+/// Every view in the order they are tabbed through
+const VIEWS: [View; 4] = [View::Overview, View::Files, View::Temps, View::Console];
 
-                Paragraph::new(
-                    [Text::Styled(
-                        format!(
-                            "{}/{}°C",
-                            state
-                            .hotend_temp
-                                .map(|t| format!("{:.2}", t))
-                                .unwrap_or("--".to_string()),
-                            state
-                                .hotend_target
-                                .map(|t| format!("{:.0}", t))
-                                .unwrap_or("--".to_string()),
-                        )
-                        .into(),
-                        style,
-                    )]
-                    .into_iter(),
-                )
-                .style(style)
-                .alignment(Alignment::Center)
-                .render(&mut f, hotend_chucks[1]);
+impl View {
+    fn name(self) -> &'static str {
+        match self {
+            View::Overview => "Overview",
+            View::Files => "Files",
+            View::Temps => "Temps",
+            View::Console => "Console",
+        }
+    }
 
-                let bed_chucks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .margin(0)
-                    .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
-                    .split(temperature_chunks[1]);
+    fn index(self) -> usize {
+        VIEWS.iter().position(|&view| view == self).unwrap_or(0)
+    }
 
-                Paragraph::new([Text::Styled("Bed".into(), style)].into_iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, bed_chucks[0]);
+    fn next(self) -> View {
+        VIEWS[(self.index() + 1) % VIEWS.len()]
+    }
 
-                Paragraph::new(
-                    [Text::Styled(
-                        format!(
-                            "{}/{}°C",
-                            state
-                                .bed_temp
-                                .map(|t| format!("{:.2}", t))
-                                .unwrap_or("--".to_string()),
-                            state
-                                .bed_target
-                                .map(|t| format!("{:.0}", t))
-                                .unwrap_or("--".to_string()),
-                        )
-                        .into(),
-                        style,
-                    )]
-                    .into_iter(),
-                )
-                .style(style)
-                .alignment(Alignment::Center)
-                .render(&mut f, bed_chucks[1]);
+    fn previous(self) -> View {
+        VIEWS[(self.index() + VIEWS.len() - 1) % VIEWS.len()]
+    }
+}
 
-                let time_chunks = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .margin(0)
-                    .constraints(
-                        [
-                            Constraint::Ratio(1, 3),
-                            Constraint::Ratio(1, 3),
-                            Constraint::Ratio(1, 3),
-                        ]
-                        .as_ref(),
-                    )
-                    .split(times_chunk);
+/// What a key does outside of the jog, console and input modes
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Quit,
+    Help,
+    Palette,
+    Pause,
+    Resume,
+    Cancel,
+    Jog,
+    Console,
+    Files,
+    Connection,
+    Home,
+    ToolTemperature,
+    BedTemperature,
+    PauseAtZ,
+    Webcam,
+    WebcamPreview,
+    Units,
+    Profiles,
+    BedMesh,
+    Presets,
+    Events,
+    Queue,
+    History,
+    Stats,
+    Power,
+    Shutdown,
+    FocusTemperature,
+    BabystepUp,
+    BabystepDown,
+    RaiseTemperature,
+    LowerTemperature,
+    Refresh,
+    NextView,
+    PreviousView,
+    View(View),
+}
 
-                let print_time = match state.print_time {
-                    Some(s) => {
-                        let (hours, minutes, seconds) = seconds_to_time(s);
-                        format!("{:.0}:{:02.0}:{:02.0}", hours, minutes, seconds)
-                    }
-                    None => "--:--:--".to_string(),
-                };
+/// Every key binding, which the help overlay lists
+const KEY_BINDINGS: [(Key, Action, &str); 40] = [
+    (Key::Char('?'), Action::Help, "Show this help"),
+    (
+        Key::Char(':'),
+        Action::Palette,
+        "Search for a command to run",
+    ),
+    (
+        Key::Ctrl('p'),
+        Action::Palette,
+        "Search for a command to run",
+    ),
+    (Key::Char('\t'), Action::NextView, "Switch to the next view"),
+    (
+        Key::BackTab,
+        Action::PreviousView,
+        "Switch to the previous view",
+    ),
+    (
+        Key::Char('1'),
+        Action::View(View::Overview),
+        "Show the overview",
+    ),
+    (Key::Char('2'), Action::View(View::Files), "Show the files"),
+    (
+        Key::Char('3'),
+        Action::View(View::Temps),
+        "Show the temperatures",
+    ),
+    (
+        Key::Char('4'),
+        Action::View(View::Console),
+        "Show the G-code console",
+    ),
+    (Key::Char('q'), Action::Quit, "Quit"),
+    (Key::Esc, Action::Quit, "Quit, or close the open pane"),
+    (
+        Key::Char(' '),
+        Action::Refresh,
+        "Refresh the job and printer state now",
+    ),
+    (Key::Char('p'), Action::Pause, "Pause the print"),
+    (Key::Char('r'), Action::Resume, "Resume the print"),
+    (
+        Key::Char('c'),
+        Action::Cancel,
+        "Cancel the print (press twice)",
+    ),
+    (
+        Key::Char('f'),
+        Action::Files,
+        "Browse files, enter to print",
+    ),
+    (Key::Char('g'), Action::Console, "Open the G-code console"),
+    (Key::Char('e'), Action::Events, "Show recent printer events"),
+    (
+        Key::Char('Q'),
+        Action::Queue,
+        "Show the Continuous Print queue",
+    ),
+    (
+        Key::Char('y'),
+        Action::History,
+        "Show today's print history",
+    ),
+    (
+        Key::Char('d'),
+        Action::Stats,
+        "Show how the updates from the server are going",
+    ),
+    (
+        Key::Char('t'),
+        Action::ToolTemperature,
+        "Set the hotend target",
+    ),
+    (Key::Char('b'), Action::BedTemperature, "Set the bed target"),
+    (
+        Key::Char('T'),
+        Action::FocusTemperature,
+        "Focus the next hotend or bed target",
+    ),
+    (
+        Key::Char('+'),
+        Action::RaiseTemperature,
+        "Raise the focused target by 5°",
+    ),
+    (
+        Key::Char('-'),
+        Action::LowerTemperature,
+        "Lower the focused target by 5°",
+    ),
+    (
+        Key::Char('z'),
+        Action::PauseAtZ,
+        "Pause the print at a Z height",
+    ),
+    (Key::Char('H'), Action::Presets, "Preheat from a preset"),
+    (
+        Key::Char('u'),
+        Action::Units,
+        "Switch between celsius and fahrenheit",
+    ),
+    (Key::Char('j'), Action::Jog, "Jog the print head"),
+    (
+        Key::Char(']'),
+        Action::BabystepUp,
+        "Babystep the nozzle away from the bed",
+    ),
+    (
+        Key::Char('['),
+        Action::BabystepDown,
+        "Babystep the nozzle toward the bed",
+    ),
+    (Key::Char('h'), Action::Home, "Home all axes"),
+    (
+        Key::Char('w'),
+        Action::Power,
+        "Turn the printer power on or off",
+    ),
+    (
+        Key::Char('O'),
+        Action::Shutdown,
+        "Disconnect, then turn the power off",
+    ),
+    (Key::Char('m'), Action::BedMesh, "Show the bed mesh"),
+    (
+        Key::Char('v'),
+        Action::Webcam,
+        "Open the webcam stream in the browser",
+    ),
+    (
+        Key::Char('V'),
+        Action::WebcamPreview,
+        "Show the webcam preview (with --webcam)",
+    ),
+    (
+        Key::Char('o'),
+        Action::Connection,
+        "Connect or disconnect the printer",
+    ),
+    (
+        Key::Char('P'),
+        Action::Profiles,
+        "Switch to another printer profile",
+    ),
+];
 
-                let print_time_chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .margin(0)
-                    .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
-                    .split(time_chunks[0]);
+/// The bed mesh colors from the lowest point to the highest
+const MESH_COLORS: [Color; 5] = [
+    Color::Blue,
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Red,
+];
 
-                Paragraph::new([Text::Styled("Print Time".into(), style)].iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, print_time_chunks[0]);
+/// How many degrees, in the units shown, + and - change the focused target by
+const TEMPERATURE_NUDGE: f64 = 5.0;
 
-                Paragraph::new([Text::Styled(print_time.into(), style)].iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, print_time_chunks[1]);
+/// How far from its target, in °C, a temperature can be and still count as at temperature
+const AT_TARGET_DEGREES: f64 = 2.0;
 
-                let estimated_time = match state.estimated_time {
-                    Some(s) => {
-                        let (hours, minutes, seconds) = seconds_to_time(s);
-                        format!("{:.0}:{:2.0}:{:2.0}", hours, minutes, seconds)
-                    }
-                    None => "--:--:--".to_string(),
-                };
+/// How far below its target, in °C, a heating temperature shows as nearly there
+const NEAR_TARGET_DEGREES: f64 = 10.0;
 
-                let estimated_time_chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .margin(0)
-                    .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
-                    .split(time_chunks[1]);
+/// How much filament, in m, beyond what the print needs the spool must have to not warn
+const LOW_FILAMENT_MARGIN: f64 = 1.0;
 
-                Paragraph::new([Text::Styled("Estimated Time".into(), style)].iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, estimated_time_chunks[0]);
+/// The completion, in percent, before which the pace against the estimate isn't shown
+const PACE_MIN_COMPLETION: f64 = 5.0;
 
-                Paragraph::new([Text::Styled(estimated_time.into(), style)].iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, estimated_time_chunks[1]);
+/// How many poll intervals without an update before the update age turns yellow
+const STALE_YELLOW_UPDATES: u32 = 2;
 
-                let remaining_time = match state.remaining_time {
-                    Some(s) => {
-                        let (hours, minutes, seconds) = seconds_to_time(s);
-                        format!("{:.0}:{:2.0}:{:2.0}", hours, minutes, seconds)
-                    }
-                    None => "--:--:--".to_string(),
-                };
+/// How many poll intervals without an update before the update age turns red
+const STALE_RED_UPDATES: u32 = 5;
 
-                let remaining_time_chunks = Layout::default()
-                    .direction(Direction::Vertical)
-                    .margin(0)
-                    .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
-                    .split(time_chunks[2]);
+/// The color of values that may be out of date because the last update failed
+const STALE_COLOR: Color = Color::DarkGray;
 
-                Paragraph::new([Text::Styled("Remaining Time".into(), style)].iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, remaining_time_chunks[0]);
+/// The smallest terminal that fits the status, the progress bar and the input line
+const MIN_WIDTH: u16 = 20;
+const MIN_HEIGHT: u16 = 3;
 
-                Paragraph::new([Text::Styled(remaining_time.into(), style)].iter())
-                    .style(style)
-                    .alignment(Alignment::Center)
-                    .render(&mut f, remaining_time_chunks[1]);
-
-                if state.progress > 0.0 {
-                    Gauge::default()
-                        .style(
-                            Style::default()
-                                .fg(Color::White)
-                                .bg(Color::Black)
-                                .modifier(Modifier::ITALIC),
-                        )
-                        .label(&format!("{:.2}%", state.progress))
-                        .percent(state.progress as u16)
-                        .render(&mut f, progress_chunk);
-                }
-            })
-            .expect("Could not draw to terminal");
+/// The frames of the spinner drawn in place of the progress bar while connecting
+const SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+
+/// Terminals shorter than this draw the compact overview even without --compact
+const COMPACT_HEIGHT: u16 = 16;
+
+/// How many lines PageUp and PageDown scroll the console
+const CONSOLE_PAGE_LINES: isize = 10;
+
+/// The most sent commands kept in the console scrollback
+const CONSOLE_HISTORY_LENGTH: usize = 100;
+
+/// The most events kept in the events pane
+const EVENT_HISTORY_LENGTH: usize = 100;
+
+/// The rows of the help that aren't bindings: the borders, the header and the gap under it
+const HELP_CHROME: u16 = 4;
+
+/// The most temperature samples kept for the chart
+const TEMPERATURE_HISTORY_LENGTH: usize = 120;
+
+/// What kind of thing happened to the printer
+#[derive(Clone, Copy, PartialEq)]
+enum EventKind {
+    Started,
+    Paused,
+    Resumed,
+    Finished,
+    Cancelled,
+    Error,
+    Connected,
+    Disconnected,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Started => "Started",
+            EventKind::Paused => "Paused",
+            EventKind::Resumed => "Resumed",
+            EventKind::Finished => "Finished",
+            EventKind::Cancelled => "Cancelled",
+            EventKind::Error => "Error",
+            EventKind::Connected => "Connected",
+            EventKind::Disconnected => "Disconnected",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            EventKind::Started | EventKind::Resumed | EventKind::Connected => Color::Cyan,
+            EventKind::Finished => Color::Green,
+            EventKind::Paused | EventKind::Cancelled => Color::Yellow,
+            EventKind::Error | EventKind::Disconnected => Color::Red,
+        }
     }
 }
 
-fn seconds_to_time(seconds: f64) -> (u64, u64, f64) {
-    let hours = (seconds / (60.0 * 60.0)) as u64;
-    let seconds = seconds % (60.0 * 60.0);
-    let minutes = (seconds / 60.0) as u64;
-    let seconds = seconds % 60.0;
+/// Something the ui has to do outside of its state after an update
+enum Effect {
+    /// Send a command to OctoPrint
+    Command(Command),
+    /// Ring the bell and run the notify command
+    NotifyComplete,
+    /// Run a hook command for a printer event
+    Hook(String, EventKind),
+}
 
-    (hours, minutes, seconds)
+/// How the polls of the job and printer state have gone, for spotting a flaky network
+///
+/// Push updates and the other requests aren't counted, so this stays at zero while the push
+/// socket is connected.
+#[derive(Clone, Default)]
+struct PollStats {
+    successes: u64,
+    failures: u64,
+    /// Failures since the last success
+    consecutive_failures: u64,
+    last_error: Option<&'static str>,
+}
+
+impl PollStats {
+    fn polls(&self) -> u64 {
+        self.successes + self.failures
+    }
+}
+
+/// Something that happened to the printer, worked out from changes in its state
+#[derive(Clone)]
+struct Event {
+    time: DateTime<Local>,
+    kind: EventKind,
+    message: String,
+}
+
+#[derive(Clone)]
+struct TemperatureSample {
+    time: Instant,
+    hotend_temp: Option<f64>,
+    hotend_target: Option<f64>,
+    bed_temp: Option<f64>,
+    bed_target: Option<f64>,
+}
+
+#[derive(Clone)]
+struct HotendState {
+    index: usize,
+    temp: Option<f64>,
+    target: Option<f64>,
+    offset: Option<f64>,
+}
+
+impl HotendState {
+    /// Whether this is an extra tool that is parked, like the second head of an IDEX printer.
+    /// OctoPrint doesn't say which tool is active, so the first one always counts as active.
+    fn is_inactive(&self) -> bool {
+        self.index != 0 && self.temp == Some(0.0) && self.target == Some(0.0)
+    }
+}
+
+/// A temperature target that + and - can change, in the order they are drawn
+#[derive(Clone, Copy, PartialEq)]
+enum TemperatureField {
+    Tool(usize),
+    Bed,
+}
+
+#[derive(Clone)]
+enum InputTarget {
+    ToolTemperature(usize),
+    BedTemperature,
+    PauseAtZ,
+}
+
+/// Where the connect picker is, first choosing the port and then the baudrate
+#[derive(Clone)]
+enum ConnectStep {
+    Port,
+    Baudrate { port: Option<String> },
+}
+
+/// Where the shutdown is, from disconnecting the printer to turning its power off
+#[derive(Clone, Copy, PartialEq)]
+enum Shutdown {
+    /// The disconnect was sent, waiting for the printer to close
+    Disconnecting,
+    /// The printer closed and the power off was sent
+    PoweringOff,
+}
+
+/// The command palette, which runs the action of any key binding found by its description
+#[derive(Clone)]
+struct Palette {
+    filter: String,
+    selected: usize,
+}
+
+/// A number being typed in by the user
+#[derive(Clone)]
+struct Input {
+    target: InputTarget,
+    text: String,
+}
+
+struct UiState {
+    progress: f64,
+    filename: Option<String>,
+    status: Option<String>,
+    print_time: Option<f64>,
+    estimated_time: Option<f64>,
+    /// The slicer's estimate, which the pace is measured against even once the file has been
+    /// printed before
+    sliced_time: Option<f64>,
+    remaining_time: Option<f64>,
+    filament_length: Option<f64>,
+    filament_volume: Option<f64>,
+    hotends: Vec<HotendState>,
+    bed_temp: Option<f64>,
+    bed_target: Option<f64>,
+    bed_offset: Option<f64>,
+    chamber_temp: Option<f64>,
+    chamber_target: Option<f64>,
+    /// The last failed command, shown until a key is pressed
+    error: Option<(DateTime<Local>, String)>,
+    /// The last failed poll, shown until an update comes through
+    poll_error: Option<(DateTime<Local>, String)>,
+    temperature_history: VecDeque<TemperatureSample>,
+    files: Vec<FileInfo>,
+    sd_files: Vec<FileInfo>,
+    sd_ready: bool,
+    view: View,
+    selected_file: usize,
+    input: Option<Input>,
+    jog_mode: bool,
+    jog_step: f64,
+    babystep: f64,
+    /// The Z offset the babysteps add up to since the printer connected, in mm
+    babystep_offset: f64,
+    connection: Option<ConnectionState>,
+    connection_options: Option<ConnectionOptions>,
+    connect_step: Option<ConnectStep>,
+    selected_connect: usize,
+    printer_closed: bool,
+    /// Set when an update fails, so the values shown may be out of date
+    stale: bool,
+    theme: Theme,
+    /// Set by the first press of an action that needs confirming, the second must come before
+    /// this to go ahead
+    confirm_deadline: Option<(Action, Instant)>,
+    printer_error: Option<String>,
+    console_input: String,
+    console_history: VecDeque<String>,
+    /// How many lines the console is scrolled up from the newest
+    console_scroll: usize,
+    help_open: bool,
+    /// How many bindings the help is scrolled down by
+    help_scroll: usize,
+    palette: Option<Palette>,
+    units: TemperatureUnit,
+    current_z: Option<f64>,
+    /// The selected file's path, to load its details again when it changes
+    file_path: Option<String>,
+    /// From the selected file's slicer settings
+    layer_height: Option<f64>,
+    nozzle_temperature: Option<f64>,
+    /// The height to pause the print at, in mm, which is watched for here since neither
+    /// OctoPrint nor the firmware can queue a pause at a height
+    pause_at_z: Option<f64>,
+    /// When Z first reached the pause height without dropping below it again
+    pause_z_reached: Option<Instant>,
+    /// Whether updates come from the push socket, the only place OctoPrint reports Z
+    push_active: bool,
+    layers: Option<LayerProgress>,
+    psu: Option<bool>,
+    shutdown: Option<Shutdown>,
+    focused_temperature: Option<TemperatureField>,
+    flags: Option<PrinterFlags>,
+    notify_command: Option<String>,
+    hooks: Hooks,
+    spool_length: Option<f64>,
+    show_inactive_tools: bool,
+    job_updated: Option<Instant>,
+    state_updated: Option<Instant>,
+    poll_interval: Duration,
+    idle_poll_interval: Duration,
+    tuning: Tuning,
+    profiles: Vec<String>,
+    profile: Option<usize>,
+    profiles_open: bool,
+    selected_profile: usize,
+    presets: Vec<Preset>,
+    presets_open: bool,
+    selected_preset: usize,
+    mesh_open: bool,
+    webcam_enabled: bool,
+    sections: Sections,
+    idle_dim: Option<Duration>,
+    temperature_format: TemperatureFormat,
+    compact: bool,
+    metrics: Option<SharedMetrics>,
+    /// The last key press or update while printing, which the idle dimming counts from
+    last_activity: Instant,
+    webcam: Option<Snapshot>,
+    webcam_loaded: bool,
+    webcam_open: bool,
+    events: VecDeque<Event>,
+    events_open: bool,
+    /// How many events the pane is scrolled up from the newest
+    events_scroll: usize,
+    stats: PollStats,
+    stats_open: bool,
+    /// Set by the first job update, until which the progress bar is a spinner
+    connected: bool,
+    queue: Option<ContinuousPrintState>,
+    queue_open: bool,
+    selected_queue_job: usize,
+    history: Option<HistorySummary>,
+    history_open: bool,
+    bed_mesh: Option<Vec<Vec<f64>>>,
+    bed_mesh_loaded: bool,
+}
+
+impl UiState {
+    /// The files shown in the file browser, local files first then the SD card
+    fn browser_files(&self) -> impl Iterator<Item = &FileInfo> {
+        self.files.iter().chain(self.sd_files.iter())
+    }
+
+    /// Adds a line to the console, dropping the oldest once it is full
+    fn push_console_line(&mut self, line: String) {
+        if self.console_history.len() >= CONSOLE_HISTORY_LENGTH {
+            self.console_history.pop_front();
+        }
+        self.console_history.push_back(line);
+
+        // Follow new lines at the bottom, but hold still if scrolled up to read something
+        if self.console_scroll > 0 {
+            self.console_scroll = (self.console_scroll + 1).min(self.console_history.len() - 1);
+        }
+    }
+
+    /// Scrolls the console up by `lines`, or down if negative
+    fn scroll_console(&mut self, lines: isize) {
+        let max = self.console_history.len().saturating_sub(1) as isize;
+        self.console_scroll = (self.console_scroll as isize + lines).max(0).min(max) as usize;
+    }
+
+    /// Adds an event to the feed, dropping the oldest once it is full
+    fn push_event(&mut self, kind: EventKind, message: String) {
+        if self.events.len() >= EVENT_HISTORY_LENGTH {
+            self.events.pop_front();
+        }
+        self.events.push_back(Event {
+            time: Local::now(),
+            kind,
+            message,
+        });
+
+        if self.events_scroll > 0 {
+            self.events_scroll = (self.events_scroll + 1).min(self.events.len() - 1);
+        }
+    }
+
+    /// Forgets everything about the printer, for when switching to another one
+    fn clear_printer(&mut self) {
+        self.progress = 0.0;
+        self.filename = None;
+        self.status = None;
+        self.print_time = None;
+        self.estimated_time = None;
+        self.sliced_time = None;
+        self.remaining_time = None;
+        self.filament_length = None;
+        self.filament_volume = None;
+        self.hotends = Vec::new();
+        self.bed_temp = None;
+        self.bed_target = None;
+        self.bed_offset = None;
+        self.chamber_temp = None;
+        self.chamber_target = None;
+        self.error = None;
+        self.poll_error = None;
+        self.temperature_history.clear();
+        self.files = Vec::new();
+        self.sd_files = Vec::new();
+        self.sd_ready = false;
+        self.selected_file = 0;
+        self.connection = None;
+        self.connection_options = None;
+        self.connect_step = None;
+        self.printer_closed = false;
+        self.stale = false;
+        self.printer_error = None;
+        self.current_z = None;
+        self.file_path = None;
+        self.layer_height = None;
+        self.nozzle_temperature = None;
+        self.pause_at_z = None;
+        self.pause_z_reached = None;
+        self.layers = None;
+        self.psu = None;
+        self.shutdown = None;
+        self.focused_temperature = None;
+        self.babystep_offset = 0.0;
+        self.flags = None;
+        self.job_updated = None;
+        self.state_updated = None;
+        self.tuning = Tuning::default();
+        self.bed_mesh = None;
+        self.bed_mesh_loaded = false;
+        self.webcam = None;
+        self.webcam_loaded = false;
+        self.events.clear();
+        self.events_scroll = 0;
+        self.stats = PollStats::default();
+        self.connected = false;
+        self.queue = None;
+        self.selected_queue_job = 0;
+        self.history = None;
+    }
+
+    /// The jobs in every print queue, in the order they are printed
+    fn queued_jobs(&self) -> impl Iterator<Item = &QueuedJob> {
+        self.queue
+            .iter()
+            .flat_map(|queue| queue.queues.iter())
+            .flat_map(|queue| queue.jobs.iter())
+    }
+
+    /// The style for values from the printer, grayed out while they may be out of date
+    fn value_style(&self, style: Style) -> Style {
+        if self.stale {
+            self.theme.fg(STALE_COLOR)
+        } else {
+            style
+        }
+    }
+
+    /// How much faster than the slicer's estimate the print is going, in percent, negative when it is
+    /// behind. Nothing early in the print, where a few seconds swing it wildly.
+    fn pace(&self) -> Option<f64> {
+        if self.progress < PACE_MIN_COMPLETION {
+            return None;
+        }
+        match (self.print_time, self.sliced_time) {
+            (Some(print_time), Some(estimated_time)) if print_time > 0.0 => {
+                let expected = estimated_time * self.progress / 100.0;
+                Some((expected / print_time - 1.0) * 100.0)
+            }
+            _ => None,
+        }
+    }
+
+    /// Shares the latest readings with the metrics server
+    fn update_metrics(&self) {
+        if let Some(metrics) = &self.metrics {
+            *metrics.lock().expect("Metrics lock poisoned") = Metrics {
+                tools: self
+                    .hotends
+                    .iter()
+                    .map(|hotend| (hotend.index, hotend.temp, hotend.target))
+                    .collect(),
+                bed_temp: self.bed_temp,
+                bed_target: self.bed_target,
+                progress: self.job_updated.map(|_| self.progress),
+                print_time_left: self.remaining_time,
+            };
+        }
+    }
+
+    /// Whether a key binding's action does anything, since some need a plugin
+    fn action_available(&self, action: Action) -> bool {
+        match action {
+            Action::Power => self.psu.is_some(),
+            Action::Queue => self.queue.is_some(),
+            Action::History => self.history.is_some(),
+            Action::PauseAtZ => self.push_active,
+            _ => true,
+        }
+    }
+
+    /// The key bindings listed in the help, leaving out the ones for things OctoPrint doesn't have
+    fn help_bindings(&self) -> impl Iterator<Item = &(Key, Action, &'static str)> {
+        KEY_BINDINGS
+            .iter()
+            .filter(move |&&(_, action, _)| self.action_available(action))
+    }
+
+    /// The key bindings whose description has the palette's filter in it, without the palette's
+    /// own
+    fn palette_bindings(&self, filter: &str) -> Vec<(Key, Action, &'static str)> {
+        let filter = filter.to_lowercase();
+        KEY_BINDINGS
+            .iter()
+            .cloned()
+            .filter(|&(_, action, description)| match action {
+                Action::Palette => false,
+                _ => self.action_available(action) && description.to_lowercase().contains(&filter),
+            })
+            .collect()
+    }
+
+    /// Whether a pane is open on the overview, which the compact overview has no room for
+    fn pane_open(&self) -> bool {
+        self.connect_step.is_some()
+            || self.presets_open
+            || self.profiles_open
+            || self.mesh_open
+            || self.webcam_open
+            || self.events_open
+            || self.stats_open
+            || self.queue_open
+            || self.history_open
+    }
+
+    /// Whether the ui has been idle long enough to dim
+    fn dimmed(&self) -> bool {
+        match self.idle_dim {
+            Some(idle_dim) => !self.printing() && self.last_activity.elapsed() >= idle_dim,
+            None => false,
+        }
+    }
+
+    fn printing(&self) -> bool {
+        self.flags.as_ref().map(|f| f.printing).unwrap_or(false)
+    }
+
+    /// When the older of the job and printer state was last updated
+    fn updated(&self) -> Option<Instant> {
+        match (self.job_updated, self.state_updated) {
+            (Some(job), Some(state)) => Some(job.min(state)),
+            _ => None,
+        }
+    }
+
+    /// The hotends drawn, leaving out parked tools unless those are shown
+    fn shown_hotends(&self) -> impl Iterator<Item = &HotendState> {
+        let show_inactive_tools = self.show_inactive_tools;
+        self.hotends
+            .iter()
+            .filter(move |hotend| show_inactive_tools || !hotend.is_inactive())
+    }
+
+    /// The temperatures that can be focused, in the order they are drawn
+    fn temperature_fields(&self) -> Vec<TemperatureField> {
+        let mut fields: Vec<TemperatureField> = self
+            .shown_hotends()
+            .map(|hotend| TemperatureField::Tool(hotend.index))
+            .collect();
+        if self.sections.bed && self.bed_target.is_some() {
+            fields.push(TemperatureField::Bed);
+        }
+        fields
+    }
+
+    /// Turns the power off once a shutdown has closed the printer, or finishes the shutdown
+    /// there without the PSU Control plugin
+    fn continue_shutdown(&mut self, effects: &mut Vec<Effect>) {
+        if self.shutdown == Some(Shutdown::Disconnecting) && self.printer_closed {
+            if self.psu.is_some() {
+                self.shutdown = Some(Shutdown::PoweringOff);
+                effects.push(Effect::Command(Command::PsuOff));
+            } else {
+                self.shutdown = None;
+            }
+        }
+    }
+
+    fn clamp_selected_file(&mut self) {
+        let count = self.browser_files().count();
+        if self.selected_file >= count {
+            self.selected_file = count.saturating_sub(1);
+        }
+    }
+
+    /// Updates the state from an event, returning what the ui has to do outside of the state
+    fn apply(&mut self, event: &UiEvent) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        match event {
+            UiEvent::JobUpdate(job) => {
+                self.progress = clamp_percent(job.progress.completion.unwrap_or(0.0));
+                self.filename = job.job.file.name.clone();
+                if job.job.file.path != self.file_path {
+                    self.file_path = job.job.file.path.clone();
+                    self.layer_height = None;
+                    self.nozzle_temperature = None;
+                    if let (Some(origin), Some(path)) = (&job.job.file.origin, &self.file_path) {
+                        effects.push(Effect::Command(Command::LoadFileDetail {
+                            origin: origin.clone(),
+                            path: path.clone(),
+                        }));
+                    }
+                }
+                self.print_time = job.progress.print_time;
+                self.estimated_time = job.job.last_print_time.or(job.job.estimated_print_time);
+                self.sliced_time = job.job.estimated_print_time;
+                self.remaining_time = job.progress.print_time_left;
+                self.filament_length = job.job.filament.as_ref().and_then(|f| f.length);
+                self.filament_volume = job.job.filament.as_ref().and_then(|f| f.volume);
+                self.job_updated = Some(Instant::now());
+                self.connected = true;
+                self.stale = false;
+                self.poll_error = None;
+            }
+            UiEvent::StateUpdate(state) => {
+                if let Some(printer_state) = &state.state {
+                    self.printer_closed = printer_state.flags.closed_or_error;
+                    let flags = &printer_state.flags;
+                    // The state text says why, like `Error: MINTEMP triggered`, and is kept
+                    // until the next print starts so the reason isn't lost when it clears
+                    if flags.error
+                        || (flags.closed_or_error && printer_state.text.contains("Error"))
+                    {
+                        self.printer_error = Some(printer_state.text.clone());
+                    } else if flags.printing && !self.printing() {
+                        self.printer_error = None;
+                    }
+
+                    if let Some(previous) = &self.flags {
+                        for (kind, message) in flag_events(
+                            previous,
+                            flags,
+                            &printer_state.text,
+                            self.filename.as_ref(),
+                            self.progress,
+                        ) {
+                            if let Some(hook) = self.hooks.command(kind).cloned() {
+                                effects.push(Effect::Hook(hook, kind));
+                            }
+                            // A queued pause is for the print that ended
+                            if kind == EventKind::Finished || kind == EventKind::Cancelled {
+                                self.pause_at_z = None;
+                            }
+                            // The firmware forgets babysteps when it resets
+                            if kind == EventKind::Disconnected {
+                                self.babystep_offset = 0.0;
+                            }
+                            self.push_event(kind, message);
+                        }
+                    }
+
+                    // A cancelled print stops printing without reaching 100%
+                    let was_printing = self.printing();
+                    if was_printing && !printer_state.flags.printing && self.progress >= 100.0 {
+                        effects.push(Effect::NotifyComplete);
+                    }
+                    self.flags = Some(printer_state.flags.clone());
+                }
+                if self.printing() {
+                    self.last_activity = Instant::now();
+                }
+                self.continue_shutdown(&mut effects);
+                self.state_updated = Some(Instant::now());
+                self.stale = false;
+                self.current_z = state.current_z;
+                if let (Some(target), Some(z)) = (self.pause_at_z, self.current_z) {
+                    if self.printing() && z >= target {
+                        let reached = *self.pause_z_reached.get_or_insert_with(Instant::now);
+                        if reached.elapsed() >= PAUSE_AT_Z_HOLD {
+                            self.pause_at_z = None;
+                            self.pause_z_reached = None;
+                            self.push_event(EventKind::Paused, format!("Reached Z {:.2} mm", z));
+                            effects.push(Effect::Command(Command::PauseJob));
+                        }
+                    } else {
+                        self.pause_z_reached = None;
+                    }
+                }
+                self.status = state.state.as_ref().map(|s| s.text.clone());
+                self.sd_ready = state.sd.as_ref().map(|sd| sd.ready).unwrap_or(false);
+                if !self.sd_ready && !self.sd_files.is_empty() {
+                    self.sd_files.clear();
+                    self.clamp_selected_file();
+                }
+                match &state.temperature {
+                    Some(temperature) => {
+                        // Only the tools OctoPrint reports get a column
+                        self.hotends = [&temperature.tool0, &temperature.tool1, &temperature.tool2]
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(index, tool)| {
+                                tool.as_ref().map(|tool| HotendState {
+                                    index,
+                                    temp: Some(tool.actual),
+                                    target: Some(tool.target),
+                                    offset: tool.offset,
+                                })
+                            })
+                            .collect();
+                        self.bed_temp = temperature.bed.as_ref().map(|b| b.actual);
+                        self.bed_target = temperature.bed.as_ref().map(|b| b.target);
+                        self.bed_offset = temperature.bed.as_ref().and_then(|b| b.offset);
+                        self.chamber_temp = temperature.chamber.as_ref().map(|c| c.actual);
+                        self.chamber_target = temperature.chamber.as_ref().map(|c| c.target);
+                    }
+                    None => {
+                        self.hotends = Vec::new();
+                        self.bed_temp = None;
+                        self.bed_target = None;
+                        self.bed_offset = None;
+                        self.chamber_temp = None;
+                        self.chamber_target = None;
+                    }
+                }
+                self.poll_error = None;
+
+                if self.temperature_history.len() >= TEMPERATURE_HISTORY_LENGTH {
+                    self.temperature_history.pop_front();
+                }
+                self.temperature_history.push_back(TemperatureSample {
+                    time: Instant::now(),
+                    hotend_temp: self.hotends.first().and_then(|h| h.temp),
+                    hotend_target: self.hotends.first().and_then(|h| h.target),
+                    bed_temp: self.bed_temp,
+                    bed_target: self.bed_target,
+                });
+            }
+            UiEvent::ConnectionUpdate(connection) => {
+                self.printer_closed = connection.current.state == "Closed";
+                self.connection = Some(connection.current.clone());
+                self.connection_options = connection.options.clone();
+                self.continue_shutdown(&mut effects);
+            }
+            UiEvent::FilesUpdate(files) => {
+                self.files = Vec::new();
+                flatten_files(files.files.clone(), &mut self.files);
+                self.files.sort_by(|a, b| a.path.cmp(&b.path));
+                self.clamp_selected_file();
+            }
+            UiEvent::SdFilesUpdate(files) => {
+                self.sd_files = Vec::new();
+                if self.sd_ready {
+                    flatten_files(files.files.clone(), &mut self.sd_files);
+                    self.sd_files.sort_by(|a, b| a.path.cmp(&b.path));
+                }
+                self.clamp_selected_file();
+            }
+            UiEvent::TuningUpdate(tuning) => self.tuning = tuning.clone(),
+            UiEvent::LayerUpdate(layers) => self.layers = *layers,
+            UiEvent::QueueUpdate(queue) => {
+                self.queue = queue.clone();
+                if self.queue.is_none() {
+                    self.queue_open = false;
+                }
+                let count = self.queued_jobs().count();
+                self.selected_queue_job = self.selected_queue_job.min(count.saturating_sub(1));
+            }
+            UiEvent::HistoryUpdate(history) => {
+                self.history = *history;
+                if self.history.is_none() {
+                    self.history_open = false;
+                }
+            }
+            UiEvent::PsuUpdate(psu) => {
+                self.psu = *psu;
+                if self.shutdown == Some(Shutdown::PoweringOff) && *psu != Some(true) {
+                    self.shutdown = None;
+                }
+            }
+            UiEvent::BedMeshUpdate(mesh) => {
+                self.bed_mesh = mesh.clone();
+                self.bed_mesh_loaded = true;
+            }
+            // A slow response may be for a file that is no longer selected
+            UiEvent::FileDetailUpdate(file) if Some(&file.path) == self.file_path.as_ref() => {
+                self.layer_height = file.layer_height();
+                self.nozzle_temperature = file.nozzle_temperature();
+            }
+            UiEvent::FileDetailUpdate(_) => {}
+            UiEvent::WebcamUpdate(snapshot) => {
+                self.webcam = snapshot.clone();
+                self.webcam_loaded = true;
+            }
+            UiEvent::Polled => {
+                self.stats.successes += 1;
+                self.stats.consecutive_failures = 0;
+            }
+            UiEvent::PushActive(active) => {
+                self.push_active = *active;
+                if !active && self.pause_at_z.take().is_some() {
+                    self.pause_z_reached = None;
+                    self.error = Some((
+                        Local::now(),
+                        "Pause at Z cleared, Z isn't reported without push updates".to_string(),
+                    ));
+                }
+            }
+            UiEvent::ProfileSwitched(index) => {
+                self.clear_printer();
+                self.profile = Some(*index);
+            }
+            // Keys are handled by the ui, and nothing changes for the others but drawing picks up
+            // the new size and time
+            UiEvent::Key(_) | UiEvent::Resize | UiEvent::Tick => {}
+            UiEvent::Error(err) => {
+                warn!("{}", err);
+                let error = Some((Local::now(), err.to_string()));
+                // A failed command says nothing about whether the printer data is current, and
+                // stays up until a key is pressed rather than the next update hiding it
+                match err {
+                    UiError::Poll(poll_err) => {
+                        self.stale = true;
+                        self.stats.failures += 1;
+                        self.stats.consecutive_failures += 1;
+                        self.stats.last_error = Some(poll_err.kind());
+                        self.poll_error = error;
+                    }
+                    UiError::Stalled(_) => {
+                        self.stale = true;
+                        self.poll_error = error;
+                    }
+                    _ => self.error = error,
+                }
+            }
+        }
+
+        effects
+    }
+}
+
+pub struct Ui<B: Backend> {
+    terminal: Terminal<B>,
+    state: UiState,
+    commands: mpsc::Sender<Command>,
+    quitting: bool,
+    /// Whether the last frame was drawn dimmed
+    drawn_dimmed: bool,
+    /// Whether the last event changed nothing worth drawing
+    skip_draw: bool,
+}
+
+impl<B: Backend> Ui<B> {
+    pub fn new(
+        mut terminal: Terminal<B>,
+        commands: mpsc::Sender<Command>,
+        options: UiOptions,
+    ) -> Ui<B> {
+        terminal.clear().expect("Could not clear terminal");
+        terminal.hide_cursor().expect("Could not hide cursor");
+
+        let state = UiState {
+            progress: 0.0,
+            filename: None,
+            status: None,
+            print_time: None,
+            estimated_time: None,
+            sliced_time: None,
+            remaining_time: None,
+            filament_length: None,
+            filament_volume: None,
+            hotends: Vec::new(),
+            bed_temp: None,
+            bed_target: None,
+            bed_offset: None,
+            chamber_temp: None,
+            chamber_target: None,
+            error: None,
+            poll_error: None,
+            temperature_history: VecDeque::with_capacity(TEMPERATURE_HISTORY_LENGTH),
+            files: Vec::new(),
+            sd_files: Vec::new(),
+            sd_ready: false,
+            view: options.view,
+            selected_file: 0,
+            input: None,
+            jog_mode: false,
+            jog_step: options.jog_step,
+            babystep: options.babystep,
+            babystep_offset: 0.0,
+            connection: None,
+            connection_options: None,
+            connect_step: None,
+            selected_connect: 0,
+            printer_closed: false,
+            stale: false,
+            theme: options.theme,
+            confirm_deadline: None,
+            printer_error: None,
+            console_input: String::new(),
+            console_history: VecDeque::with_capacity(CONSOLE_HISTORY_LENGTH),
+            console_scroll: 0,
+            help_open: false,
+            help_scroll: 0,
+            palette: None,
+            units: options.units,
+            current_z: None,
+            file_path: None,
+            layer_height: None,
+            nozzle_temperature: None,
+            pause_at_z: None,
+            pause_z_reached: None,
+            push_active: false,
+            layers: None,
+            psu: None,
+            shutdown: None,
+            focused_temperature: None,
+            flags: None,
+            notify_command: options.notify_command,
+            hooks: options.hooks,
+            spool_length: options.spool_length,
+            show_inactive_tools: options.show_inactive_tools,
+            job_updated: None,
+            state_updated: None,
+            poll_interval: options.poll_interval,
+            idle_poll_interval: options.idle_poll_interval,
+            tuning: Tuning::default(),
+            profiles: options.profiles,
+            profile: options.profile,
+            profiles_open: false,
+            selected_profile: options.profile.unwrap_or(0),
+            presets: options.presets,
+            presets_open: false,
+            selected_preset: 0,
+            mesh_open: false,
+            webcam_enabled: options.webcam,
+            sections: options.sections,
+            idle_dim: options.idle_dim,
+            temperature_format: options.temperature_format,
+            compact: options.compact,
+            metrics: options.metrics,
+            last_activity: Instant::now(),
+            webcam: None,
+            webcam_loaded: false,
+            webcam_open: false,
+            events: VecDeque::with_capacity(EVENT_HISTORY_LENGTH),
+            events_open: false,
+            events_scroll: 0,
+            stats: PollStats::default(),
+            stats_open: false,
+            connected: false,
+            queue: None,
+            queue_open: false,
+            selected_queue_job: 0,
+            history: None,
+            history_open: false,
+            bed_mesh: None,
+            bed_mesh_loaded: false,
+        };
+
+        Ui {
+            terminal,
+            state,
+            commands,
+            quitting: false,
+            drawn_dimmed: false,
+            skip_draw: false,
+        }
+    }
+
+    /// Whether the user has asked to quit
+    pub fn quitting(&self) -> bool {
+        self.quitting
+    }
+
+    /// What to remember for the next run
+    pub fn saved_state(&self) -> SavedState {
+        SavedState {
+            view: Some(self.state.view),
+            profile: self
+                .state
+                .profile
+                .and_then(|index| self.state.profiles.get(index))
+                .cloned(),
+        }
+    }
+
+    fn send_command(&mut self, command: Command) {
+        if let Err(e) = self.commands.try_send(command) {
+            self.state.error = Some((Local::now(), format!("Could not send command: {}", e)));
+        }
+    }
+
+    /// Rings the terminal bell and runs the notify command when a print completes
+    fn notify_complete(&mut self) {
+        print!("\x07");
+        let _ = io::stdout().flush();
+
+        if let Some(notify_command) = self.state.notify_command.clone() {
+            self.run_command("notify command", &notify_command, EventKind::Finished);
+        }
+    }
+
+    /// Starts a shell command without waiting for it, with the file name, progress and event in
+    /// $OCTOPRINT_FILE, $OCTOPRINT_PROGRESS and $OCTOPRINT_EVENT
+    fn run_command(&mut self, what: &str, command: &str, kind: EventKind) {
+        let result = process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env(
+                "OCTOPRINT_FILE",
+                self.state.filename.clone().unwrap_or_default(),
+            )
+            .env("OCTOPRINT_PROGRESS", format!("{:.2}", self.state.progress))
+            .env("OCTOPRINT_EVENT", kind.name())
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn();
+        if let Err(e) = result {
+            warn!("Could not run {}: {}", what, e);
+            self.state.error = Some((Local::now(), format!("Could not run {}: {}", what, e)));
+        }
+    }
+
+    /// Scrolls the help down by `lines`, or up if negative, stopping once the last binding shows
+    fn scroll_help(&mut self, lines: isize) {
+        let height = self.terminal.size().map(|size| size.height).unwrap_or(0);
+        let visible = height.saturating_sub(HELP_CHROME) as usize;
+        let max = self.state.help_bindings().count().saturating_sub(visible) as isize;
+        self.state.help_scroll = (self.state.help_scroll as isize + lines).max(0).min(max) as usize;
+    }
+
+    fn set_view(&mut self, view: View) {
+        // The panes are drawn on the overview, so they can't stay open behind another view
+        if view != View::Overview {
+            self.state.connect_step = None;
+            self.state.presets_open = false;
+            self.state.profiles_open = false;
+            self.state.mesh_open = false;
+            self.state.webcam_open = false;
+            self.state.events_open = false;
+            self.state.stats_open = false;
+            self.state.queue_open = false;
+            self.state.history_open = false;
+        }
+        if view == View::Files && self.state.view != View::Files {
+            self.send_command(Command::LoadFiles);
+            if self.state.sd_ready {
+                self.send_command(Command::LoadSdFiles);
+            }
+        }
+        self.state.view = view;
+    }
+
+    fn handle_input_key(&mut self, mut input: Input, key: Key) {
+        match key {
+            Key::Char(c) if c.is_ascii_digit() || c == '.' => {
+                input.text.push(c);
+                self.state.input = Some(input);
+            }
+            Key::Backspace => {
+                input.text.pop();
+                self.state.input = Some(input);
+            }
+            Key::Esc => {}
+            Key::Char('\n') => {
+                // An empty or invalid number just closes the input, or clears a queued pause
+                match (input.target, input.text.parse()) {
+                    (InputTarget::ToolTemperature(tool), Ok(target)) => {
+                        let target = self.state.units.to_celsius(target);
+                        self.send_command(Command::SetToolTemperature { tool, target })
+                    }
+                    (InputTarget::BedTemperature, Ok(target)) => {
+                        let target = self.state.units.to_celsius(target);
+                        self.send_command(Command::SetBedTemperature { target })
+                    }
+                    (InputTarget::PauseAtZ, Ok(z)) => {
+                        self.state.pause_at_z = Some(z);
+                        self.state.pause_z_reached = None;
+                    }
+                    (InputTarget::PauseAtZ, Err(_)) => self.state.pause_at_z = None,
+                    (_, Err(_)) => {}
+                }
+            }
+            _ => self.state.input = Some(input),
+        }
+    }
+
+    /// Moves Z by `z` mm with M290, which the firmware adds on top of the G-code while printing
+    fn babystep(&mut self, z: f64) {
+        self.state.babystep_offset += z;
+        self.send_command(Command::SendGcode {
+            commands: vec![format!("M290 Z{:.3}", z)],
+        });
+    }
+
+    /// Changes the focused target by `degrees` in the units shown and sends it right away
+    fn nudge_temperature(&mut self, degrees: f64) {
+        let units = self.state.units;
+        let nudged = |target: Option<f64>| {
+            let target = units.from_celsius(target.unwrap_or(0.0)) + degrees;
+            units.to_celsius(target).max(0.0)
+        };
+        match self.state.focused_temperature {
+            Some(TemperatureField::Tool(tool)) => {
+                let hotend = self.state.hotends.iter_mut().find(|h| h.index == tool);
+                if let Some(hotend) = hotend {
+                    let target = nudged(hotend.target);
+                    // Shown right away so quick presses add up before the next update
+                    hotend.target = Some(target);
+                    self.send_command(Command::SetToolTemperature { tool, target });
+                }
+            }
+            Some(TemperatureField::Bed) => {
+                let target = nudged(self.state.bed_target);
+                self.state.bed_target = Some(target);
+                self.send_command(Command::SetBedTemperature { target });
+            }
+            None => {
+                self.state.error = Some((
+                    Local::now(),
+                    "Press T to focus a temperature first".to_string(),
+                ));
+            }
+        }
+    }
+
+    /// Handles a key in the command palette, which closes once a command is picked
+    fn handle_palette_key(&mut self, mut palette: Palette, key: Key) {
+        let bindings = self.state.palette_bindings(&palette.filter);
+        match key {
+            Key::Esc => {}
+            // Cancelling still needs c pressed again, as from its key
+            Key::Char('\n') => {
+                if let Some(&(_, action, _)) = bindings.get(palette.selected) {
+                    self.run_action(action, false);
+                }
+            }
+            Key::Up => {
+                palette.selected = palette.selected.saturating_sub(1);
+                self.state.palette = Some(palette);
+            }
+            Key::Down => {
+                if palette.selected + 1 < bindings.len() {
+                    palette.selected += 1;
+                }
+                self.state.palette = Some(palette);
+            }
+            Key::Backspace => {
+                palette.filter.pop();
+                palette.selected = 0;
+                self.state.palette = Some(palette);
+            }
+            Key::Char(c) => {
+                palette.filter.push(c);
+                palette.selected = 0;
+                self.state.palette = Some(palette);
+            }
+            _ => self.state.palette = Some(palette),
+        }
+    }
+
+    /// Handles a key in jog mode, returning false if the key isn't a jog key
+    fn handle_jog_key(&mut self, key: Key) -> bool {
+        let step = self.state.jog_step;
+        let (x, y, z) = match key {
+            Key::Left => (-step, 0.0, 0.0),
+            Key::Right => (step, 0.0, 0.0),
+            Key::Up => (0.0, step, 0.0),
+            Key::Down => (0.0, -step, 0.0),
+            Key::PageUp => (0.0, 0.0, step),
+            Key::PageDown => (0.0, 0.0, -step),
+            Key::Char('+') => {
+                self.state.jog_step = JOG_STEPS
+                    .iter()
+                    .cloned()
+                    .find(|&s| s > step)
+                    .unwrap_or(step);
+                return true;
+            }
+            Key::Char('-') => {
+                self.state.jog_step = JOG_STEPS
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .find(|&s| s < step)
+                    .unwrap_or(step);
+                return true;
+            }
+            Key::Char('j') | Key::Esc => {
+                self.state.jog_mode = false;
+                return true;
+            }
+            _ => return false,
+        };
+        self.send_command(Command::Jog { x, y, z });
+        true
+    }
+
+    fn handle_console_key(&mut self, key: Key) {
+        match key {
+            Key::Esc => self.set_view(View::Overview),
+            Key::Char('\n') => {
+                let line = self.state.console_input.trim().to_string();
+                self.state.console_input.clear();
+                if !line.is_empty() {
+                    self.state.push_console_line(line.clone());
+                    self.send_command(Command::SendGcode {
+                        commands: vec![line],
+                    });
+                }
+            }
+            Key::Backspace => {
+                self.state.console_input.pop();
+            }
+            Key::Up => self.state.scroll_console(1),
+            Key::Down => self.state.scroll_console(-1),
+            Key::PageUp => self.state.scroll_console(CONSOLE_PAGE_LINES),
+            Key::PageDown => self.state.scroll_console(-CONSOLE_PAGE_LINES),
+            Key::Char(c) => self.state.console_input.push(c),
+            _ => {}
+        }
+    }
+
+    fn handle_preset_key(&mut self, key: Key) {
+        match key {
+            Key::Esc => self.state.presets_open = false,
+            Key::Up => self.state.selected_preset = self.state.selected_preset.saturating_sub(1),
+            Key::Down => {
+                if self.state.selected_preset + 1 < self.state.presets.len() {
+                    self.state.selected_preset += 1;
+                }
+            }
+            Key::Char('\n') => {
+                self.state.presets_open = false;
+                if let Some(preset) = self.state.presets.get(self.state.selected_preset).cloned() {
+                    // Every hotend heats, or just the first if none have reported yet
+                    let tools: Vec<usize> = if self.state.hotends.is_empty() {
+                        vec![0]
+                    } else {
+                        self.state.hotends.iter().map(|h| h.index).collect()
+                    };
+                    for tool in tools {
+                        self.send_command(Command::SetToolTemperature {
+                            tool,
+                            target: preset.tool,
+                        });
+                    }
+                    self.send_command(Command::SetBedTemperature { target: preset.bed });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_connect_key(&mut self, step: ConnectStep, key: Key) {
+        let options = self.state.connection_options.clone().unwrap_or_default();
+        // Auto comes first, then whatever OctoPrint lists
+        let choices = match step {
+            ConnectStep::Port => options.ports.len() + 1,
+            ConnectStep::Baudrate { .. } => options.baudrates.len() + 1,
+        };
+        let selected = self.state.selected_connect;
+
+        match key {
+            Key::Esc => return,
+            Key::Up => self.state.selected_connect = selected.saturating_sub(1),
+            Key::Down => {
+                if selected + 1 < choices {
+                    self.state.selected_connect += 1;
+                }
+            }
+            Key::Char('\n') => {
+                self.state.selected_connect = 0;
+                match step {
+                    ConnectStep::Port => {
+                        let port = selected
+                            .checked_sub(1)
+                            .and_then(|i| options.ports.get(i).cloned());
+                        self.state.connect_step = Some(ConnectStep::Baudrate { port });
+                    }
+                    ConnectStep::Baudrate { port } => {
+                        let baudrate = selected
+                            .checked_sub(1)
+                            .and_then(|i| options.baudrates.get(i).cloned());
+                        self.send_command(Command::Connect { port, baudrate });
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+        self.state.connect_step = Some(step);
+    }
+
+    fn handle_profile_key(&mut self, key: Key) {
+        match key {
+            Key::Esc => self.state.profiles_open = false,
+            Key::Up => self.state.selected_profile = self.state.selected_profile.saturating_sub(1),
+            Key::Down => {
+                if self.state.selected_profile + 1 < self.state.profiles.len() {
+                    self.state.selected_profile += 1;
+                }
+            }
+            Key::Char('\n') => {
+                self.state.profiles_open = false;
+                if self.state.profile != Some(self.state.selected_profile) {
+                    self.send_command(Command::SwitchProfile {
+                        index: self.state.selected_profile,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_key(&mut self, key: Key) {
+        // The arrows and page keys scroll the help, and any other key closes it
+        if self.state.help_open {
+            match key {
+                Key::Up => self.scroll_help(-1),
+                Key::Down => self.scroll_help(1),
+                Key::PageUp => self.scroll_help(-10),
+                Key::PageDown => self.scroll_help(10),
+                _ => self.state.help_open = false,
+            }
+            return;
+        }
+
+        if let Some(input) = self.state.input.take() {
+            self.handle_input_key(input, key);
+            return;
+        }
+
+        if let Some(palette) = self.state.palette.take() {
+            self.handle_palette_key(palette, key);
+            return;
+        }
+
+        // Tab switches views from anywhere, even while typing in the console
+        if key == Key::Char('\t') {
+            self.run_action(Action::NextView, false);
+            return;
+        }
+        if key == Key::BackTab {
+            self.run_action(Action::PreviousView, false);
+            return;
+        }
+
+        if self.state.view == View::Console {
+            self.handle_console_key(key);
+            return;
+        }
+
+        if self.state.profiles_open {
+            self.handle_profile_key(key);
+            return;
+        }
+
+        if self.state.presets_open {
+            self.handle_preset_key(key);
+            return;
+        }
+
+        if let Some(step) = self.state.connect_step.take() {
+            self.handle_connect_key(step, key);
+            return;
+        }
+
+        if self.state.jog_mode && self.handle_jog_key(key) {
+            return;
+        }
+
+        // Any other key gives up on what was waiting to be confirmed
+        let confirming = match self.state.confirm_deadline.take() {
+            Some((action, deadline)) if Instant::now() < deadline => Some(action),
+            _ => None,
+        };
+
+        match key {
+            Key::Esc if self.state.view == View::Files => self.set_view(View::Overview),
+            Key::Esc if self.state.mesh_open => self.state.mesh_open = false,
+            Key::Esc if self.state.webcam_open => self.state.webcam_open = false,
+            Key::Esc if self.state.events_open => self.state.events_open = false,
+            Key::Esc if self.state.stats_open => self.state.stats_open = false,
+            Key::Esc if self.state.queue_open => self.state.queue_open = false,
+            Key::Esc if self.state.history_open => self.state.history_open = false,
+            Key::Up if self.state.queue_open => {
+                self.state.selected_queue_job = self.state.selected_queue_job.saturating_sub(1);
+            }
+            Key::Down if self.state.queue_open => {
+                if self.state.selected_queue_job + 1 < self.state.queued_jobs().count() {
+                    self.state.selected_queue_job += 1;
+                }
+            }
+            Key::Esc if self.state.shutdown.is_some() => self.state.shutdown = None,
+            Key::Up if self.state.events_open => {
+                let max = self.state.events.len().saturating_sub(1);
+                self.state.events_scroll = (self.state.events_scroll + 1).min(max);
+            }
+            Key::Down if self.state.events_open => {
+                self.state.events_scroll = self.state.events_scroll.saturating_sub(1);
+            }
+            Key::Up if self.state.view == View::Files => {
+                self.state.selected_file = self.state.selected_file.saturating_sub(1);
+            }
+            Key::Down if self.state.view == View::Files => {
+                if self.state.selected_file + 1 < self.state.browser_files().count() {
+                    self.state.selected_file += 1;
+                }
+            }
+            Key::Char('\n') if self.state.view == View::Files => {
+                self.set_view(View::Overview);
+                // The new status shows up with the next state update
+                let file = self
+                    .state
+                    .browser_files()
+                    .nth(self.state.selected_file)
+                    .cloned();
+                if let Some(file) = file {
+                    self.send_command(Command::SelectFile {
+                        origin: file.origin,
+                        path: file.path,
+                    });
+                }
+            }
+            _ => {
+                let binding = KEY_BINDINGS.iter().find(|binding| binding.0 == key);
+                if let Some(&(_, action, _)) = binding {
+                    self.run_action(action, confirming == Some(action));
+                }
+            }
+        }
+    }
+
+    /// Runs what a key is bound to, with `confirmed` set when it is the second press of an
+    /// action that needs confirming
+    fn run_action(&mut self, action: Action, confirmed: bool) {
+        match action {
+            // Nothing watches for the height once the ui has quit
+            Action::Quit if self.state.pause_at_z.is_some() && !confirmed => {
+                self.state.confirm_deadline = Some((action, Instant::now() + CONFIRM_TIME))
+            }
+            Action::Quit => self.quitting = true,
+            Action::Help => {
+                self.state.help_open = true;
+                self.state.help_scroll = 0;
+            }
+            Action::Palette => {
+                self.state.palette = Some(Palette {
+                    filter: String::new(),
+                    selected: 0,
+                })
+            }
+            Action::Pause => self.send_command(Command::PauseJob),
+            Action::Resume => self.send_command(Command::ResumeJob),
+            // Cancelling needs a second press so a stray key can't end a print
+            Action::Cancel if confirmed => self.send_command(Command::CancelJob),
+            Action::Cancel => {
+                self.state.confirm_deadline = Some((action, Instant::now() + CONFIRM_TIME))
+            }
+            Action::Jog => self.state.jog_mode = true,
+            Action::Console => self.set_view(View::Console),
+            Action::Files if self.state.view == View::Files => self.set_view(View::Overview),
+            Action::Files => self.set_view(View::Files),
+            Action::NextView => self.set_view(self.state.view.next()),
+            Action::PreviousView => self.set_view(self.state.view.previous()),
+            Action::View(view) => self.set_view(view),
+            Action::Connection => {
+                if self.state.printer_closed {
+                    self.set_view(View::Overview);
+                    self.state.connect_step = Some(ConnectStep::Port);
+                    self.state.selected_connect = 0;
+                } else {
+                    self.send_command(Command::Disconnect);
+                }
+            }
+            Action::Home => self.send_command(Command::Home {
+                axes: vec![PrinterAxis::X, PrinterAxis::Y, PrinterAxis::Z],
+            }),
+            Action::ToolTemperature => {
+                self.state.input = Some(Input {
+                    target: InputTarget::ToolTemperature(0),
+                    text: String::new(),
+                });
+            }
+            Action::BedTemperature => {
+                self.state.input = Some(Input {
+                    target: InputTarget::BedTemperature,
+                    text: String::new(),
+                });
+            }
+            // Without the push socket there is no Z to watch
+            Action::PauseAtZ if !self.state.push_active => {
+                self.state.error = Some((
+                    Local::now(),
+                    "Pause at Z needs push updates, which aren't connected".to_string(),
+                ))
+            }
+            Action::PauseAtZ => {
+                self.state.input = Some(Input {
+                    target: InputTarget::PauseAtZ,
+                    text: String::new(),
+                });
+            }
+            Action::Webcam => self.send_command(Command::OpenWebcam),
+            Action::Units => self.state.units = self.state.units.toggled(),
+            // Without the PSU Control plugin there is nothing to switch
+            Action::Power => match self.state.psu {
+                Some(true) if self.state.printing() => {
+                    self.state.error = Some((
+                        Local::now(),
+                        "Can't turn the power off while printing".to_string(),
+                    ));
+                }
+                Some(true) => self.send_command(Command::PsuOff),
+                Some(false) => self.send_command(Command::PsuOn),
+                None => {}
+            },
+            Action::Shutdown if self.state.printing() => {
+                self.state.error =
+                    Some((Local::now(), "Can't shut down while printing".to_string()));
+            }
+            Action::Shutdown if self.state.printer_closed && self.state.psu != Some(true) => {
+                self.state.error = Some((
+                    Local::now(),
+                    "The printer is already disconnected and off".to_string(),
+                ));
+            }
+            // Already disconnected, so only the power is left
+            Action::Shutdown if self.state.printer_closed => {
+                self.state.shutdown = Some(Shutdown::PoweringOff);
+                self.send_command(Command::PsuOff);
+            }
+            Action::Shutdown => {
+                self.state.shutdown = Some(Shutdown::Disconnecting);
+                self.send_command(Command::Disconnect);
+            }
+            Action::FocusTemperature => {
+                let fields = self.state.temperature_fields();
+                let next = match self.state.focused_temperature {
+                    Some(focused) => fields
+                        .iter()
+                        .position(|&field| field == focused)
+                        .map(|index| index + 1)
+                        .unwrap_or(0),
+                    None => 0,
+                };
+                // Going past the last field takes the focus away
+                self.state.focused_temperature = fields.get(next).cloned();
+            }
+            Action::BabystepUp => self.babystep(self.state.babystep),
+            Action::BabystepDown => self.babystep(-self.state.babystep),
+            Action::RaiseTemperature => self.nudge_temperature(TEMPERATURE_NUDGE),
+            Action::LowerTemperature => self.nudge_temperature(-TEMPERATURE_NUDGE),
+            Action::Refresh => self.send_command(Command::Refresh),
+            Action::Events => {
+                self.set_view(View::Overview);
+                self.state.events_open = !self.state.events_open;
+                self.state.events_scroll = 0;
+            }
+            Action::Queue => {
+                if self.state.queue.is_none() {
+                    self.state.error = Some((
+                        Local::now(),
+                        "The Continuous Print plugin isn't installed".to_string(),
+                    ));
+                } else {
+                    self.set_view(View::Overview);
+                    self.state.queue_open = !self.state.queue_open;
+                    self.state.selected_queue_job = 0;
+                }
+            }
+            Action::History => {
+                if self.state.history.is_none() {
+                    self.state.error = Some((
+                        Local::now(),
+                        "The Print History plugin isn't installed".to_string(),
+                    ));
+                } else {
+                    self.set_view(View::Overview);
+                    self.state.history_open = !self.state.history_open;
+                }
+            }
+            Action::Stats => {
+                self.set_view(View::Overview);
+                self.state.stats_open = !self.state.stats_open;
+            }
+            Action::Presets => {
+                self.set_view(View::Overview);
+                self.state.presets_open = true;
+                self.state.selected_preset = 0;
+            }
+            Action::BedMesh => {
+                self.set_view(View::Overview);
+                self.state.mesh_open = !self.state.mesh_open;
+                if self.state.mesh_open {
+                    self.send_command(Command::LoadBedMesh);
+                }
+            }
+            Action::WebcamPreview => {
+                if self.state.webcam_enabled {
+                    self.set_view(View::Overview);
+                    self.state.webcam_open = !self.state.webcam_open;
+                } else {
+                    self.state.error = Some((
+                        Local::now(),
+                        "Start with --webcam to preview the webcam".to_string(),
+                    ));
+                }
+            }
+            Action::Profiles => {
+                if self.state.profiles.is_empty() {
+                    self.state.error =
+                        Some((Local::now(), "No profiles in the config file".to_string()));
+                } else {
+                    self.set_view(View::Overview);
+                    self.state.profiles_open = true;
+                    self.state.selected_profile = self.state.profile.unwrap_or(0);
+                }
+            }
+        }
+    }
+
+    /// Handles a key, or updates the state and does whatever the update calls for
+    pub fn handle(&mut self, event: UiEvent) {
+        // While dimmed only the clock would change each tick, so the screen is left alone
+        self.skip_draw = match event {
+            UiEvent::Tick => self.drawn_dimmed && self.state.dimmed(),
+            _ => false,
+        };
+        if let UiEvent::Key(key) = event {
+            // The key that wakes a dimmed ui doesn't do anything else, since it was pressed blind
+            let dimmed = self.state.dimmed();
+            self.state.last_activity = Instant::now();
+            if !dimmed {
+                self.state.error = None;
+                self.handle_key(key);
+            }
+            return;
+        }
+        for effect in self.state.apply(&event) {
+            match effect {
+                Effect::Command(command) => self.send_command(command),
+                Effect::NotifyComplete => self.notify_complete(),
+                Effect::Hook(hook, kind) => self.run_command("hook", &hook, kind),
+            }
+        }
+        self.state.update_metrics();
+    }
+
+    pub fn draw(&mut self) {
+        if self.skip_draw {
+            return;
+        }
+
+        self.drawn_dimmed = self.state.dimmed();
+        self.state.theme = self.state.theme.dimmed(self.drawn_dimmed);
+        let state = &self.state;
+
+        self.terminal
+            .draw(|mut f| {
+                let size = f.size();
+                let now = Instant::now();
+
+                let style = state.theme.base();
+
+                Block::default().style(style).render(&mut f, size);
+
+                if size.width < MIN_WIDTH || size.height < MIN_HEIGHT {
+                    Paragraph::new([Text::Styled("Terminal too small".into(), style)].iter())
+                        .style(style)
+                        .alignment(Alignment::Center)
+                        .wrap(true)
+                        .render(&mut f, size);
+                    return;
+                }
+
+                // The compact layout leaves out the view tabs, and the full overview when
+                // no pane needs the room
+                let compact = state.compact || size.height < COMPACT_HEIGHT;
+                let tabs_height = if compact { 0 } else { 1 };
+
+                // The mode line and the view tabs go first on short terminals
+                let heights = fit_rows(
+                    size.height,
+                    &[(1, 2), (1, 0), (tabs_height, 3), (1, 0), (1, 0)],
+                );
+
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .margin(0)
+                    .constraints(
+                        [
+                            Constraint::Length(heights[0]),
+                            Constraint::Length(heights[1]), // Status
+                            Constraint::Length(heights[2]), // Views
+                            Constraint::Min(heights[3]),
+                            Constraint::Length(heights[4]),
+                        ]
+                        .as_ref(),
+                    )
+                    .split(size);
+
+                let mode_chunk = chunks[0];
+                let status_chunk = chunks[1];
+                let views_chunk = chunks[2];
+                let view_chunk = chunks[3];
+                let error_chunk = chunks[4];
+
+                if let Some(profile) = state.profile.and_then(|p| state.profiles.get(p)) {
+                    Paragraph::new([Text::Styled(format!(" {}", profile).into(), style)].iter())
+                        .style(style)
+                        .alignment(Alignment::Left)
+                        .render(&mut f, mode_chunk);
+                }
+
+                let updated = match state.updated() {
+                    Some(updated) => {
+                        let age = now.duration_since(updated);
+                        let expected = if state.printing() {
+                            state.poll_interval
+                        } else {
+                            state.idle_poll_interval
+                        };
+                        // A missed update or two is normal, more means polling has stalled
+                        let updated_style = if age > expected * STALE_RED_UPDATES {
+                            state.theme.error()
+                        } else if age > expected * STALE_YELLOW_UPDATES {
+                            state.theme.fg(Color::Yellow)
+                        } else {
+                            style
+                        };
+                        Text::Styled(
+                            format!("updated {}s ago ", age.as_secs()).into(),
+                            updated_style,
+                        )
+                    }
+                    None => Text::Styled("never updated ".into(), state.theme.error()),
+                };
+
+                Paragraph::new(
+                    [
+                        Text::Styled(
+                            format!("{} ", Local::now().format("%H:%M:%S")).into(),
+                            style,
+                        ),
+                        updated,
+                    ]
+                    .iter(),
+                )
+                .style(style)
+                .alignment(Alignment::Right)
+                .render(&mut f, mode_chunk);
+
+                if state.jog_mode {
+                    Paragraph::new(
+                        [Text::Styled(
+                            format!(
+                                "Jog {}mm: arrows X/Y, PgUp/PgDn Z, +/- step, h home, j done",
+                                state.jog_step
+                            )
+                            .into(),
+                            state.theme.modifier(style, Modifier::BOLD),
+                        )]
+                        .iter(),
+                    )
+                    .style(style)
+                    .alignment(Alignment::Center)
+                    .render(&mut f, mode_chunk);
+                }
+
+                let shutdown = match state.shutdown {
+                    Some(Shutdown::Disconnecting) => Some("Shutdown: disconnecting (1/2)"),
+                    Some(Shutdown::PoweringOff) => Some("Shutdown: turning the power off (2/2)"),
+                    None => None,
+                };
+                let confirm = match state.confirm_deadline {
+                    Some((Action::Cancel, deadline)) if now < deadline => {
+                        Some("Press c again to confirm cancel")
+                    }
+                    Some((Action::Quit, deadline)) if now < deadline => {
+                        Some("Press q again to quit, which drops the pause at Z")
+                    }
+                    _ => None,
+                };
+                let status = match (confirm, shutdown) {
+                    (Some(confirm), _) => Text::Styled(
+                        confirm.into(),
+                        state
+                            .theme
+                            .modifier(state.theme.fg(Color::Yellow), Modifier::BOLD),
+                    ),
+                    (_, Some(shutdown)) => Text::Styled(
+                        shutdown.into(),
+                        state
+                            .theme
+                            .modifier(state.theme.fg(Color::Yellow), Modifier::BOLD),
+                    ),
+                    _ => Text::Styled(
+                        state
+                            .status
+                            .clone()
+                            .unwrap_or("No Status".to_string())
+                            .into(),
+                        state.theme.status(),
+                    ),
+                };
+
+                Paragraph::new([status].iter())
+                    .style(style)
+                    .alignment(Alignment::Center)
+                    .render(&mut f, status_chunk);
+
+                let mut chips = Vec::new();
+                // Only known with the PSU Control plugin
+                match state.psu {
+                    Some(true) => chips.push(("POWER ON", Color::Green)),
+                    Some(false) => chips.push(("POWER OFF", Color::Red)),
+                    None => {}
+                }
+                if let Some(flags) = &state.flags {
+                    chips.extend(flag_chips(flags));
+                }
+                if !chips.is_empty() {
+                    let chips: Vec<Text> = chips
+                        .into_iter()
+                        .flat_map(|(name, color)| {
+                            vec![
+                                Text::Styled(format!(" {} ", name).into(), state.theme.chip(color)),
+                                Text::Styled(" ".into(), style),
+                            ]
+                        })
+                        .collect();
+                    Paragraph::new(chips.iter())
+                        .style(style)
+                        .alignment(Alignment::Left)
+                        .render(&mut f, status_chunk);
+                }
+
+                if let Some(connection) = &state.connection {
+                    let connection = match (&connection.port, connection.baudrate) {
+                        (Some(port), Some(baudrate)) => {
+                            format!("{} {} @ {} ", connection.state, port, baudrate)
+                        }
+                        (Some(port), None) => format!("{} {} ", connection.state, port),
+                        _ => format!("{} ", connection.state),
+                    };
+                    Paragraph::new([Text::Styled(connection.into(), style)].iter())
+                        .style(style)
+                        .alignment(Alignment::Right)
+                        .render(&mut f, status_chunk);
+                }
+
+                let views: Vec<String> = VIEWS
+                    .iter()
+                    .enumerate()
+                    .map(|(index, view)| format!("{} {}", index + 1, view.name()))
+                    .collect();
+                Tabs::default()
+                    .titles(&views)
+                    .select(state.view.index())
+                    .style(style)
+                    .highlight_style(state.theme.modifier(style, Modifier::REVERSED))
+                    .render(&mut f, views_chunk);
+
+                match state.view {
+                    View::Overview if compact && !state.pane_open() => {
+                        draw_compact_overview(&mut f, view_chunk, &state, style)
+                    }
+                    View::Overview => draw_overview(&mut f, view_chunk, &state, style, now),
+                    View::Files => draw_files(&mut f, view_chunk, &state, style),
+                    View::Temps => draw_temps(&mut f, view_chunk, &state, style, now),
+                    View::Console => draw_console(&mut f, view_chunk, &state, style),
+                }
+
+                if let Some(input) = &state.input {
+                    let prompt = match input.target {
+                        InputTarget::ToolTemperature(tool) => {
+                            format!("Hotend {} target ({})", tool, state.units.symbol())
+                        }
+                        InputTarget::BedTemperature => {
+                            format!("Bed target ({})", state.units.symbol())
+                        }
+                        InputTarget::PauseAtZ => "Pause at Z (mm), empty to clear".to_string(),
+                    };
+                    Paragraph::new(
+                        [Text::Styled(
+                            format!("{}: {}_", prompt, input.text).into(),
+                            state.theme.modifier(style, Modifier::BOLD),
+                        )]
+                        .iter(),
+                    )
+                    .style(style)
+                    .alignment(Alignment::Left)
+                    .render(&mut f, error_chunk);
+                } else if let Some((time, error)) =
+                    state.error.as_ref().or(state.poll_error.as_ref())
+                {
+                    Paragraph::new(
+                        [Text::Styled(
+                            format!("{} {}", time.format("%H:%M:%S"), error).into(),
+                            state.theme.error(),
+                        )]
+                        .iter(),
+                    )
+                    .style(style)
+                    .alignment(Alignment::Left)
+                    .render(&mut f, error_chunk);
+                }
+
+                if state.help_open {
+                    let width = 60.min(size.width);
+                    let bindings: Vec<_> = state.help_bindings().collect();
+                    let height = (bindings.len() as u16 + HELP_CHROME).min(size.height);
+                    let help_area = Rect::new(
+                        (size.width - width) / 2,
+                        (size.height - height) / 2,
+                        width,
+                        height,
+                    );
+
+                    // Blank out whatever is underneath the overlay
+                    let blank = format!("{}\n", " ".repeat(width as usize)).repeat(height as usize);
+                    Paragraph::new([Text::Styled(blank.into(), style)].iter())
+                        .style(style)
+                        .render(&mut f, help_area);
+
+                    // A resize can leave the scroll past the end, so keep the last page full
+                    let visible = height.saturating_sub(HELP_CHROME) as usize;
+                    let scroll = state
+                        .help_scroll
+                        .min(bindings.len().saturating_sub(visible));
+                    let rows: Vec<Vec<String>> = bindings
+                        .iter()
+                        .skip(scroll)
+                        .map(|&&(key, _, description)| vec![key_name(key), description.to_string()])
+                        .collect();
+                    let title = if bindings.len() > visible {
+                        format!(
+                            "Help ({}-{} of {}, Up/Down to scroll)",
+                            scroll + 1,
+                            (scroll + visible).min(bindings.len()),
+                            bindings.len()
+                        )
+                    } else {
+                        "Help".to_string()
+                    };
+
+                    Table::new(
+                        ["Key", "Action"].iter(),
+                        rows.iter().map(|row| Row::Data(row.iter())),
+                    )
+                    .block(Block::default().borders(Borders::ALL).title(&title))
+                    .style(style)
+                    .header_style(state.theme.modifier(style, Modifier::BOLD))
+                    .widths(&[8, width.saturating_sub(13)])
+                    .render(&mut f, help_area);
+                }
+
+                if let Some(palette) = &state.palette {
+                    let width = 60.min(size.width);
+                    let height = 16.min(size.height);
+                    let palette_area = Rect::new(
+                        (size.width - width) / 2,
+                        (size.height - height) / 2,
+                        width,
+                        height,
+                    );
+
+                    // Blank out whatever is underneath the overlay
+                    let blank = format!("{}\n", " ".repeat(width as usize)).repeat(height as usize);
+                    Paragraph::new([Text::Styled(blank.into(), style)].iter())
+                        .style(style)
+                        .render(&mut f, palette_area);
+
+                    let commands: Vec<String> = state
+                        .palette_bindings(&palette.filter)
+                        .into_iter()
+                        .map(|(key, _, description)| format!("{} ({})", description, key_name(key)))
+                        .collect();
+                    let title = format!("Command: {}_", palette.filter);
+                    SelectableList::default()
+                        .block(Block::default().borders(Borders::ALL).title(&title))
+                        .style(style)
+                        .highlight_style(state.theme.modifier(style, Modifier::REVERSED))
+                        .highlight_symbol(">")
+                        .items(&commands)
+                        .select(Some(palette.selected))
+                        .render(&mut f, palette_area);
+                }
+            })
+            .expect("Could not draw to terminal");
+    }
+}
+
+/// Draws the overview in a few lines: the file, the temperatures on one line, the times on
+/// another and the progress bar
+fn draw_compact_overview<B: Backend>(f: &mut Frame<B>, area: Rect, state: &UiState, style: Style) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .constraints(
+            [
+                Constraint::Length(1), // Filename
+                Constraint::Length(1), // Temperatures
+                Constraint::Length(1), // Times
+                Constraint::Length(1), // Progress
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    let title = state.filename.clone().unwrap_or("No File".to_string());
+    Paragraph::new([Text::Styled(title.into(), style)].iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, chunks[0]);
+
+    let mut readings: Vec<(String, Option<f64>, Option<f64>)> = state
+        .shown_hotends()
+        .map(|hotend| (format!("T{}", hotend.index), hotend.temp, hotend.target))
+        .collect();
+    if state.sections.bed {
+        readings.push(("B".to_string(), state.bed_temp, state.bed_target));
+    }
+    if state.chamber_temp.is_some() {
+        readings.push(("C".to_string(), state.chamber_temp, state.chamber_target));
+    }
+    let units = state.units;
+    let temperatures: Vec<Text> = readings
+        .into_iter()
+        .map(|(name, temp, target)| {
+            let reading = format!(
+                "{} {}/{}{}  ",
+                name,
+                temp.map(|t| state.temperature_format.reading(units.from_celsius(t)))
+                    .unwrap_or("--".to_string()),
+                target
+                    .map(|t| format!("{:.0}", units.from_celsius(t)))
+                    .unwrap_or("--".to_string()),
+                units.symbol(),
+            );
+            Text::Styled(
+                reading.into(),
+                state.value_style(state.theme.temperature(temperature_heat(temp, target))),
+            )
+        })
+        .collect();
+    Paragraph::new(temperatures.iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, chunks[1]);
+
+    let time = |seconds: Option<f64>| match seconds {
+        Some(s) => {
+            let (hours, minutes, seconds) = seconds_to_time(s);
+            format!("{:.0}:{:02.0}:{:02.0}", hours, minutes, seconds)
+        }
+        None => "--:--:--".to_string(),
+    };
+    let times = format!(
+        "Elapsed {}  Left {}",
+        time(state.print_time),
+        time(state.remaining_time)
+    );
+    Paragraph::new([Text::Styled(times.into(), state.value_style(style))].iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, chunks[2]);
+
+    draw_progress(
+        f,
+        chunks[3],
+        state,
+        state.value_style(state.theme.gauge()),
+        style,
+    );
+}
+
+/// Draws the print progress, the temperatures, and the chart or whichever pane is open
+fn draw_overview<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    state: &UiState,
+    style: Style,
+    now: Instant,
+) {
+    let title = state.filename.clone().unwrap_or("No File".to_string());
+    // Fan, speed and flow only come from push updates, so polling alone leaves nothing to show
+    let show_tuning = state.sections.tuning && state.push_active;
+
+    // Small terminals drop the chart and the spacing first, and the progress bar last
+    let heights = fit_rows(
+        area.height,
+        &[
+            (1, 3),                                           // Printer error
+            (1, 1),                                           // Filename
+            (if state.sections.filament { 1 } else { 0 }, 5), // Filament
+            (if show_tuning { 1 } else { 0 }, 6),             // Fan, speed and flow
+            (2, 8),
+            (2, 2), // Temperatures
+            (1, 8), // Heating bars, under the temperatures
+            (5, 9), // Temperature chart
+            (2, 4), // Times
+            (1, 7), // Layer and Z
+            (1, 0), // Progress
+        ],
+    );
+    let show_chart = heights[7] > 0;
+    let show_times = heights[8] > 0;
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .constraints(
+            [
+                Constraint::Length(heights[0]),
+                Constraint::Length(heights[1]),
+                Constraint::Length(heights[2]),
+                Constraint::Length(heights[3]),
+                Constraint::Length(heights[4]),
+                Constraint::Length(heights[5] + heights[6]),
+                if show_chart {
+                    Constraint::Min(heights[7])
+                } else {
+                    Constraint::Length(0)
+                },
+                Constraint::Length(heights[8]),
+                Constraint::Length(heights[9]),
+                Constraint::Length(heights[10]),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    let printer_error_chunk = chunks[0];
+    let filename_chunk = chunks[1];
+    let filament_chunk = chunks[2];
+    let tuning_chunk = chunks[3];
+    let temperatures_chunk = chunks[5];
+    let chart_chunk = chunks[6];
+    let times_chunk = chunks[7];
+    let position_chunk = chunks[8];
+    let progress_chunk = chunks[9];
+
+    if let Some(printer_error) = &state.printer_error {
+        Paragraph::new(
+            [Text::Styled(
+                printer_error.clone().into(),
+                state.theme.modifier(state.theme.error(), Modifier::BOLD),
+            )]
+            .iter(),
+        )
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, printer_error_chunk);
+    }
+
+    let mut filename = vec![Text::Styled(title.into(), style)];
+    // Only known when the file was sliced with the settings in it and the plugin read them
+    let mut slicer = Vec::new();
+    if let Some(layer_height) = state.layer_height {
+        slicer.push(format!("{:.2} mm layers", layer_height));
+    }
+    if let Some(temperature) = state.nozzle_temperature {
+        slicer.push(format!(
+            "{:.0}{}",
+            state.units.from_celsius(temperature),
+            state.units.symbol()
+        ));
+    }
+    if !slicer.is_empty() {
+        filename.push(Text::Styled(
+            format!("  {}", slicer.join(", ")).into(),
+            state.value_style(style),
+        ));
+    }
+
+    Paragraph::new(filename.iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, filename_chunk);
+
+    let filament = match (state.filament_length, state.filament_volume) {
+        (Some(length), Some(volume)) => {
+            format!("Filament: {:.2} m / {:.1} cm³", length / 1000.0, volume)
+        }
+        (Some(length), None) => format!("Filament: {:.2} m", length / 1000.0),
+        (None, Some(volume)) => format!("Filament: {:.1} cm³", volume),
+        (None, None) => "Filament: --".to_string(),
+    };
+
+    let values = state.value_style(style);
+
+    let mut filament = vec![Text::Styled(filament.into(), values)];
+
+    // What is left on the spool against what the rest of the print needs
+    if let (Some(spool), Some(length)) = (state.spool_length, state.filament_length) {
+        let used = length / 1000.0 * state.progress / 100.0;
+        let needed = length / 1000.0 - used;
+        let left = spool - used;
+        if left < needed + LOW_FILAMENT_MARGIN {
+            filament.push(Text::Styled(
+                format!(" Low filament: {:.2} m left on spool", left).into(),
+                state
+                    .theme
+                    .modifier(state.theme.error(), Modifier::BOLD | Modifier::SLOW_BLINK),
+            ));
+        } else {
+            filament.push(Text::Styled(
+                format!(" Spool: {:.2} m left", left).into(),
+                values,
+            ));
+        }
+    }
+
+    Paragraph::new(filament.iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, filament_chunk);
+
+    let tuning = format!(
+        "Fan: {} Speed: {} Flow: {}",
+        percent_or_dashes(state.tuning.fan),
+        percent_or_dashes(state.tuning.speed),
+        percent_or_dashes(state.tuning.flow),
+    );
+
+    Paragraph::new([Text::Styled(tuning.into(), values)].iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, tuning_chunk);
+
+    draw_temperatures(f, temperatures_chunk, state, style);
+
+    if !show_chart {
+        // No room for the chart or panes
+    } else if let Some(step) = &state.connect_step {
+        let options = state.connection_options.clone().unwrap_or_default();
+        let (title, choices) = match step {
+            ConnectStep::Port => ("Connect: port", options.ports),
+            ConnectStep::Baudrate { .. } => (
+                "Connect: baudrate",
+                options.baudrates.iter().map(|b| b.to_string()).collect(),
+            ),
+        };
+        let choices: Vec<String> = std::iter::once("AUTO".to_string()).chain(choices).collect();
+        SelectableList::default()
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .style(style)
+            .highlight_style(state.theme.modifier(style, Modifier::REVERSED))
+            .highlight_symbol(">")
+            .items(&choices)
+            .select(Some(state.selected_connect))
+            .render(f, chart_chunk);
+    } else if state.presets_open {
+        let presets: Vec<String> = state
+            .presets
+            .iter()
+            .map(|preset| {
+                format!(
+                    "{} {:.0}/{:.0}",
+                    preset.name,
+                    state.units.from_celsius(preset.tool),
+                    state.units.from_celsius(preset.bed),
+                )
+            })
+            .collect();
+        SelectableList::default()
+            .block(Block::default().borders(Borders::ALL).title("Preheat"))
+            .style(style)
+            .highlight_style(state.theme.modifier(style, Modifier::REVERSED))
+            .highlight_symbol(">")
+            .items(&presets)
+            .select(Some(state.selected_preset))
+            .render(f, chart_chunk);
+    } else if state.profiles_open {
+        SelectableList::default()
+            .block(Block::default().borders(Borders::ALL).title("Profiles"))
+            .style(style)
+            .highlight_style(state.theme.modifier(style, Modifier::REVERSED))
+            .highlight_symbol(">")
+            .items(&state.profiles)
+            .select(Some(state.selected_profile))
+            .render(f, chart_chunk);
+    } else if state.mesh_open {
+        draw_bed_mesh(f, chart_chunk, &state, style);
+    } else if state.webcam_open {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("Webcam")
+            .style(style);
+        match &state.webcam {
+            Some(snapshot) => SnapshotView::new(snapshot)
+                .block(block)
+                .render(f, chart_chunk),
+            None => {
+                let message = if state.webcam_loaded {
+                    "No webcam is set up in OctoPrint"
+                } else {
+                    "Loading snapshot..."
+                };
+                Paragraph::new([Text::Styled(message.into(), style)].iter())
+                    .block(block)
+                    .style(style)
+                    .alignment(Alignment::Center)
+                    .render(f, chart_chunk);
+            }
+        }
+    } else if state.events_open {
+        let title = if state.events_scroll > 0 {
+            format!("Events ({} newer below)", state.events_scroll)
+        } else {
+            "Events".to_string()
+        };
+        let mut events_block = Block::default()
+            .borders(Borders::ALL)
+            .title(&title)
+            .style(style);
+        let events_area = events_block.inner(chart_chunk);
+        events_block.render(f, chart_chunk);
+
+        let visible = events_area.height as usize;
+        let offset = state
+            .events
+            .len()
+            .saturating_sub(visible + state.events_scroll);
+        let lines: Vec<Text> = if state.events.is_empty() {
+            vec![Text::Styled("No events yet".into(), style)]
+        } else {
+            state
+                .events
+                .iter()
+                .flat_map(|event| {
+                    vec![
+                        Text::Styled(format!("{} ", event.time.format("%H:%M:%S")).into(), style),
+                        Text::Styled(
+                            format!("{:<12} ", event.kind.name()).into(),
+                            state.theme.fg(event.kind.color()),
+                        ),
+                        Text::Styled(format!("{}\n", event.message).into(), style),
+                    ]
+                })
+                .collect()
+        };
+
+        Paragraph::new(lines.iter())
+            .style(style)
+            .scroll(offset as u16)
+            .render(f, events_area);
+    } else if state.queue_open {
+        draw_queue(f, chart_chunk, &state, style);
+    } else if let (true, Some(history)) = (state.history_open, &state.history) {
+        draw_history(f, chart_chunk, history, style);
+    } else if state.stats_open {
+        draw_stats(f, chart_chunk, &state.stats, style);
+    } else {
+        draw_temperature_chart(f, chart_chunk, state, style, now);
+    }
+
+    if show_times {
+        let columns = if state.sections.estimated { 3 } else { 2 };
+        let time_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .margin(0)
+            .constraints(vec![Constraint::Ratio(1, columns); columns as usize])
+            .split(times_chunk);
+
+        let print_time = match state.print_time {
+            Some(s) => {
+                let (hours, minutes, seconds) = seconds_to_time(s);
+                format!("{:.0}:{:02.0}:{:02.0}", hours, minutes, seconds)
+            }
+            None => "--:--:--".to_string(),
+        };
+
+        let print_time_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
+            .split(time_chunks[0]);
+
+        Paragraph::new([Text::Styled("Print Time".into(), style)].iter())
+            .style(style)
+            .alignment(Alignment::Center)
+            .render(f, print_time_chunks[0]);
+
+        Paragraph::new([Text::Styled(print_time.into(), values)].iter())
+            .style(style)
+            .alignment(Alignment::Center)
+            .render(f, print_time_chunks[1]);
+
+        if state.sections.estimated {
+            let estimated_time = match state.estimated_time {
+                Some(s) => {
+                    let (hours, minutes, seconds) = seconds_to_time(s);
+                    format!("{:.0}:{:02.0}:{:02.0}", hours, minutes, seconds)
+                }
+                None => "--:--:--".to_string(),
+            };
+            let estimated_time = match state.pace() {
+                Some(pace) if pace >= 0.0 => format!("{} ({:.0}% ahead)", estimated_time, pace),
+                Some(pace) => format!("{} ({:.0}% behind)", estimated_time, -pace),
+                None => estimated_time,
+            };
+
+            let estimated_time_chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(0)
+                .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
+                .split(time_chunks[1]);
+
+            Paragraph::new([Text::Styled("Estimated Time".into(), style)].iter())
+                .style(style)
+                .alignment(Alignment::Center)
+                .render(f, estimated_time_chunks[0]);
+
+            Paragraph::new([Text::Styled(estimated_time.into(), values)].iter())
+                .style(style)
+                .alignment(Alignment::Center)
+                .render(f, estimated_time_chunks[1]);
+        }
+
+        let remaining_time = match state.remaining_time {
+            Some(s) => {
+                let (hours, minutes, seconds) = seconds_to_time(s);
+                format!("{:.0}:{:02.0}:{:02.0}", hours, minutes, seconds)
+            }
+            None => "--:--:--".to_string(),
+        };
+
+        let done_at = match state.remaining_time {
+            Some(s) => {
+                let done = Local::now() + chrono::Duration::seconds(s as i64);
+                done.format("%H:%M").to_string()
+            }
+            None => "--:--".to_string(),
+        };
+        let remaining_time = format!("{} Done at {}", remaining_time, done_at);
+
+        let remaining_time_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(0)
+            .constraints([Constraint::Length(1), Constraint::Length(1)].as_ref())
+            .split(time_chunks[columns as usize - 1]);
+
+        Paragraph::new([Text::Styled("Remaining Time".into(), style)].iter())
+            .style(style)
+            .alignment(Alignment::Center)
+            .render(f, remaining_time_chunks[0]);
+
+        Paragraph::new([Text::Styled(remaining_time.into(), values)].iter())
+            .style(style)
+            .alignment(Alignment::Center)
+            .render(f, remaining_time_chunks[1]);
+    }
+
+    // Each is hidden when OctoPrint or the layer plugin doesn't report it
+    let mut position = Vec::new();
+    if let Some(layers) = state.layers {
+        position.push(format!("Layer {}/{}", layers.current, layers.total));
+    }
+    if let Some(z) = state.current_z {
+        position.push(format!("Z: {:.2} mm", z));
+    }
+    if let Some(z) = state.pause_at_z {
+        position.push(format!("Pause at Z: {:.2} mm", z));
+    }
+    if state.babystep_offset.abs() > 1e-6 {
+        position.push(format!("Babystep: {:+.3} mm", state.babystep_offset));
+    }
+    Paragraph::new([Text::Styled(position.join("  ").into(), values)].iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, position_chunk);
+
+    draw_progress(
+        f,
+        progress_chunk,
+        state,
+        state
+            .theme
+            .modifier(state.value_style(state.theme.gauge()), Modifier::ITALIC),
+        style,
+    );
+}
+
+/// Draws the progress bar, or a spinner until the first job update shows the server is there
+fn draw_progress<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    state: &UiState,
+    gauge_style: Style,
+    style: Style,
+) {
+    if state.connected {
+        Gauge::default()
+            .style(gauge_style)
+            .label(&format!("{:.2}%", state.progress))
+            .percent(state.progress as u16)
+            .render(f, area);
+    } else {
+        // Turns once a second with the ticks
+        let frame = SPINNER[Local::now().timestamp() as usize % SPINNER.len()];
+        Paragraph::new([Text::Styled(format!("{} Connecting…", frame).into(), style)].iter())
+            .style(style)
+            .alignment(Alignment::Center)
+            .render(f, area);
+    }
+}
+
+/// Draws a reading for each heater above the temperature chart
+fn draw_temps<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    state: &UiState,
+    style: Style,
+    now: Instant,
+) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .constraints(
+            [
+                Constraint::Length(3),
+                Constraint::Length(1),
+                Constraint::Min(0),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    draw_temperatures(f, chunks[0], state, style);
+    draw_temperature_chart(f, chunks[2], state, style, now);
+}
+
+/// Draws the current, target and offset temperatures of each hotend and the bed side by side, with
+/// heating bars under them if there is a third line
+fn draw_temperatures<B: Backend>(f: &mut Frame<B>, area: Rect, state: &UiState, style: Style) {
+    // Show an empty hotend when there is no temperature data at all
+    let hotends = if state.hotends.is_empty() {
+        vec![HotendState {
+            index: 0,
+            temp: None,
+            target: None,
+            offset: None,
+        }]
+    } else {
+        state.shown_hotends().cloned().collect()
+    };
+
+    // Open frame printers have no chamber, so it only gets a column when OctoPrint reports one
+    let show_chamber = state.chamber_temp.is_some();
+    let columns =
+        hotends.len() + if state.sections.bed { 1 } else { 0 } + if show_chamber { 1 } else { 0 };
+
+    let temperature_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .margin(0)
+        .constraints(vec![Constraint::Ratio(1, columns as u32); columns])
+        .split(area);
+
+    for (hotend, chunk) in hotends.iter().zip(temperature_chunks.iter()) {
+        let name = if hotends.len() == 1 {
+            "Hotend".to_string()
+        } else {
+            format!("Hotend {}", hotend.index)
+        };
+        draw_temperature(
+            f,
+            *chunk,
+            &name,
+            Some(TemperatureField::Tool(hotend.index)),
+            hotend.temp,
+            hotend.target,
+            hotend.offset,
+            state,
+            style,
+        );
+    }
+
+    if state.sections.bed {
+        draw_temperature(
+            f,
+            temperature_chunks[hotends.len()],
+            "Bed",
+            Some(TemperatureField::Bed),
+            state.bed_temp,
+            state.bed_target,
+            state.bed_offset,
+            state,
+            style,
+        );
+    }
+
+    if show_chamber {
+        draw_temperature(
+            f,
+            temperature_chunks[columns - 1],
+            "Chamber",
+            None,
+            state.chamber_temp,
+            state.chamber_target,
+            None,
+            state,
+            style,
+        );
+    }
+}
+
+/// Draws the temperatures and targets of the first hotend and the bed over time
+fn draw_temperature_chart<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    state: &UiState,
+    style: Style,
+    now: Instant,
+) {
+    let history = &state.temperature_history;
+
+    let units = state.units;
+    let hotend_temps = history_points(history, now, units, |s| s.hotend_temp);
+    let hotend_targets = history_points(history, now, units, |s| s.hotend_target);
+    let bed_temps = history_points(history, now, units, |s| s.bed_temp);
+    let bed_targets = history_points(history, now, units, |s| s.bed_target);
+
+    let oldest = history
+        .front()
+        .map(|s| -seconds(now.duration_since(s.time)))
+        .unwrap_or(0.0)
+        .min(-1.0);
+    let hottest = hotend_temps
+        .iter()
+        .chain(hotend_targets.iter())
+        .chain(bed_temps.iter())
+        .chain(bed_targets.iter())
+        .map(|&(_, t)| t)
+        .fold(0.0, f64::max);
+    let top = (hottest / 50.0).floor() * 50.0 + 50.0;
+
+    let x_labels = [format!("{:.0}s", oldest), "now".to_string()];
+    let y_labels = ["0".to_string(), format!("{:.0}", top)];
+
+    Chart::default()
+        .style(style)
+        .x_axis(
+            Axis::default()
+                .style(style)
+                .labels_style(style)
+                .bounds([oldest, 0.0])
+                .labels(&x_labels),
+        )
+        .y_axis(
+            Axis::default()
+                .style(style)
+                .labels_style(style)
+                .bounds([0.0, top])
+                .labels(&y_labels),
+        )
+        .datasets(&[
+            Dataset::default()
+                .name("Hotend target")
+                .marker(Marker::Dot)
+                .style(state.theme.fg(Color::LightRed))
+                .data(&hotend_targets),
+            Dataset::default()
+                .name("Bed target")
+                .marker(Marker::Dot)
+                .style(state.theme.fg(Color::LightBlue))
+                .data(&bed_targets),
+            Dataset::default()
+                .name("Hotend")
+                .marker(Marker::Braille)
+                .style(state.theme.fg(Color::Red))
+                .data(&hotend_temps),
+            Dataset::default()
+                .name("Bed")
+                .marker(Marker::Braille)
+                .style(state.theme.fg(Color::Blue))
+                .data(&bed_temps),
+        ])
+        .render(f, area);
+}
+
+/// Draws the local and SD card files to pick one to print
+fn draw_files<B: Backend>(f: &mut Frame<B>, area: Rect, state: &UiState, style: Style) {
+    let paths: Vec<String> = state
+        .browser_files()
+        .map(|file| match file.origin {
+            Origin::SdCard => format!("SD: {}", file.path),
+            _ => file.path.clone(),
+        })
+        .collect();
+    SelectableList::default()
+        .block(Block::default().borders(Borders::ALL).title("Files"))
+        .style(style)
+        .highlight_style(state.theme.modifier(style, Modifier::REVERSED))
+        .highlight_symbol(">")
+        .items(&paths)
+        .select(Some(state.selected_file))
+        .render(f, area);
+}
+
+/// Draws the sent commands above the line being typed
+fn draw_console<B: Backend>(f: &mut Frame<B>, area: Rect, state: &UiState, style: Style) {
+    let title = if state.console_scroll > 0 {
+        format!("Console ({} newer lines below)", state.console_scroll)
+    } else {
+        "Console".to_string()
+    };
+    let mut console_block = Block::default()
+        .borders(Borders::ALL)
+        .title(&title)
+        .style(style);
+    let console_area = console_block.inner(area);
+    console_block.render(f, area);
+
+    let console_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+        .split(console_area);
+
+    // Show the window of lines that ends `console_scroll` lines from the newest
+    let visible = console_chunks[0].height as usize;
+    let offset = state
+        .console_history
+        .len()
+        .saturating_sub(visible + state.console_scroll);
+    let lines: Vec<Text> = state
+        .console_history
+        .iter()
+        .map(|line| Text::Styled(format!("{}\n", line).into(), style))
+        .collect();
+
+    Paragraph::new(lines.iter())
+        .style(style)
+        .scroll(offset as u16)
+        .render(f, console_chunks[0]);
+
+    Paragraph::new(
+        [Text::Styled(
+            format!("> {}_", state.console_input).into(),
+            state.theme.modifier(style, Modifier::BOLD),
+        )]
+        .iter(),
+    )
+    .style(style)
+    .render(f, console_chunks[1]);
+}
+
+impl<B: Backend> Drop for Ui<B> {
+    /// Leaves the terminal how it was found, raw mode ends when the backend is dropped
+    fn drop(&mut self) {
+        // Keep the panic message on screen
+        if !thread::panicking() {
+            let _ = self.terminal.clear();
+            let _ = self.terminal.set_cursor(0, 0);
+        }
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// A one line summary of the print, like `benchy.gcode 43% 1:12 left 210/60`
+pub fn status_line(job: &JobResponse, state: &StateResponse, units: TemperatureUnit) -> String {
+    let filename = job.job.file.name.clone().unwrap_or("No File".to_string());
+    let progress = clamp_percent(job.progress.completion.unwrap_or(0.0));
+    let remaining = match job.progress.print_time_left {
+        Some(s) => {
+            let (hours, minutes, _) = seconds_to_time(s);
+            format!("{}:{:02}", hours, minutes)
+        }
+        None => "--:--".to_string(),
+    };
+
+    let temperature = |data: Option<&TemperatureData>| {
+        data.map(|data| format!("{:.0}", units.from_celsius(data.actual)))
+            .unwrap_or("--".to_string())
+    };
+    let temperatures = state.temperature.as_ref();
+
+    format!(
+        "{} {:.0}% {} left {}/{}",
+        filename,
+        progress,
+        remaining,
+        temperature(temperatures.and_then(|t| t.tool0.as_ref())),
+        temperature(temperatures.and_then(|t| t.bed.as_ref())),
+    )
+}
+
+/// The heights of the rows of a layout, with the least important rows left out until the rest fit
+/// in `height`. Each row is its height and its priority, where 0 is the most important.
+fn fit_rows(height: u16, rows: &[(u16, u8)]) -> Vec<u16> {
+    let mut heights: Vec<u16> = rows.iter().map(|&(height, _)| height).collect();
+    let mut least_important: Vec<usize> = (0..rows.len()).collect();
+    least_important.sort_by_key(|&row| Reverse(rows[row].1));
+    for row in least_important {
+        if heights.iter().sum::<u16>() <= height {
+            break;
+        }
+        heights[row] = 0;
+    }
+    heights
+}
+
+/// How a key is written in the help
+fn key_name(key: Key) -> String {
+    match key {
+        Key::Esc => "Esc".to_string(),
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::Char(' ') => "Space".to_string(),
+        Key::BackTab => "S-Tab".to_string(),
+        Key::Ctrl(c) => format!("C-{}", c),
+        Key::Char(c) => c.to_string(),
+        _ => format!("{:?}", key),
+    }
+}
+
+/// Draws the Continuous Print jobs in order, with the files of the selected one below
+fn draw_queue<B: Backend>(f: &mut Frame<B>, area: Rect, state: &UiState, style: Style) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+        .split(area);
+
+    let title = match &state.queue {
+        Some(queue) if queue.active => "Queue (running)",
+        _ => "Queue (stopped)",
+    };
+    let jobs: Vec<String> = state
+        .queued_jobs()
+        .enumerate()
+        .map(|(index, job)| {
+            let status = if job.acquired {
+                "Printing"
+            } else if job.remaining == 0 {
+                "Done"
+            } else {
+                "Queued"
+            };
+            format!(
+                "{:>2}. {:<8} {} ({}/{} left)",
+                index + 1,
+                status,
+                job.name,
+                job.remaining,
+                job.count
+            )
+        })
+        .collect();
+    SelectableList::default()
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .style(style)
+        .highlight_style(state.theme.modifier(style, Modifier::REVERSED))
+        .highlight_symbol(">")
+        .items(&jobs)
+        .select(Some(state.selected_queue_job))
+        .render(f, chunks[0]);
+
+    let details: Vec<Text> = match state.queued_jobs().nth(state.selected_queue_job) {
+        Some(job) => job
+            .sets
+            .iter()
+            .map(|set| {
+                Text::Styled(
+                    format!("{} ({}/{} left)\n", set.path, set.remaining, set.count).into(),
+                    style,
+                )
+            })
+            .collect(),
+        None => vec![Text::Styled("The queue is empty".into(), style)],
+    };
+    Paragraph::new(details.iter())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Files")
+                .style(style),
+        )
+        .style(style)
+        .render(f, chunks[1]);
+}
+
+/// Draws what the print history adds up to, one value to a line
+fn draw_history<B: Backend>(f: &mut Frame<B>, area: Rect, history: &HistorySummary, style: Style) {
+    let (hours, minutes, seconds) = seconds_to_time(history.print_time_today);
+    let rows = [
+        ("Prints today", history.prints_today.to_string()),
+        (
+            "Time today",
+            format!("{:.0}:{:02.0}:{:02.0}", hours, minutes, seconds),
+        ),
+        ("Succeeded", history.successes.to_string()),
+        ("Failed", history.failures.to_string()),
+    ];
+    let lines: Vec<Text> = rows
+        .iter()
+        .map(|(name, value)| Text::Styled(format!("{:<13} {}\n", name, value).into(), style))
+        .collect();
+    Paragraph::new(lines.iter())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("History")
+                .style(style),
+        )
+        .style(style)
+        .render(f, area);
+}
+
+/// Draws the update counters, one to a line
+fn draw_stats<B: Backend>(f: &mut Frame<B>, area: Rect, stats: &PollStats, style: Style) {
+    let rows = [
+        ("Polls", stats.polls().to_string()),
+        ("Successes", stats.successes.to_string()),
+        ("Failures", stats.failures.to_string()),
+        ("Failing for", stats.consecutive_failures.to_string()),
+        ("Last error", stats.last_error.unwrap_or("none").to_string()),
+    ];
+    let lines: Vec<Text> = rows
+        .iter()
+        .map(|(name, value)| Text::Styled(format!("{:<12} {}\n", name, value).into(), style))
+        .collect();
+    Paragraph::new(lines.iter())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Diagnostics")
+                .style(style),
+        )
+        .style(style)
+        .render(f, area);
+}
+
+/// Draws the bed mesh as a grid of cells colored from blue for the lowest point to red for the
+/// highest, with the back of the bed at the top
+fn draw_bed_mesh<B: Backend>(f: &mut Frame<B>, area: Rect, state: &UiState, style: Style) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Bed Mesh")
+        .style(style);
+
+    let mesh = match &state.bed_mesh {
+        Some(mesh) => mesh,
+        None => {
+            let message = if state.bed_mesh_loaded {
+                "No mesh data"
+            } else {
+                "Loading..."
+            };
+            Paragraph::new([Text::Styled(message.into(), style)].iter())
+                .block(block)
+                .style(style)
+                .alignment(Alignment::Center)
+                .render(f, area);
+            return;
+        }
+    };
+
+    let values = mesh.iter().flat_map(|row| row.iter().cloned());
+    let lowest = values.clone().fold(std::f64::INFINITY, f64::min);
+    let highest = values.fold(std::f64::NEG_INFINITY, f64::max);
+    let range = (highest - lowest).max(std::f64::EPSILON);
+
+    let mut text = Vec::new();
+    for row in mesh.iter().rev() {
+        for &value in row {
+            let level = ((value - lowest) / range * (MESH_COLORS.len() - 1) as f64).round();
+            let color = MESH_COLORS[level as usize];
+            text.push(Text::Styled(
+                format!("{:^7.3}", value).into(),
+                state.theme.chip(color),
+            ));
+        }
+        text.push(Text::Styled("\n".into(), style));
+    }
+    text.push(Text::Styled(
+        format!("\nRange: {:.3} mm", highest - lowest).into(),
+        style,
+    ));
+
+    Paragraph::new(text.iter())
+        .block(block)
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, area);
+}
+
+/// The events between two sets of printer flags
+fn flag_events(
+    old: &PrinterFlags,
+    new: &PrinterFlags,
+    text: &str,
+    filename: Option<&String>,
+    progress: f64,
+) -> Vec<(EventKind, String)> {
+    let file = filename.cloned().unwrap_or_else(|| "the print".to_string());
+    let mut events = Vec::new();
+
+    if old.closed_or_error && !new.closed_or_error {
+        events.push((EventKind::Connected, text.to_string()));
+    }
+    // OctoPrint clears printing while paused, so a pause isn't the print ending
+    if !old.printing && !old.paused && new.printing {
+        events.push((EventKind::Started, file.clone()));
+    } else if old.paused && new.printing {
+        events.push((EventKind::Resumed, file.clone()));
+    } else if old.printing && new.paused {
+        events.push((EventKind::Paused, file.clone()));
+    } else if (old.printing || old.paused) && !new.printing && !new.paused {
+        if progress >= 100.0 {
+            events.push((EventKind::Finished, file.clone()));
+        } else {
+            events.push((
+                EventKind::Cancelled,
+                format!("{} at {:.0}%", file, progress),
+            ));
+        }
+    }
+    if !old.error && new.error {
+        events.push((EventKind::Error, text.to_string()));
+    }
+    if !old.closed_or_error && new.closed_or_error {
+        events.push((EventKind::Disconnected, text.to_string()));
+    }
+
+    events
+}
+
+/// The printer flags that are set, as names and the colors to show them in
+fn flag_chips(flags: &PrinterFlags) -> Vec<(&'static str, Color)> {
+    [
+        (flags.operational, "OPERATIONAL", Color::Green),
+        (flags.ready, "READY", Color::Green),
+        (flags.printing, "PRINTING", Color::Cyan),
+        (flags.pausing, "PAUSING", Color::Yellow),
+        (flags.paused, "PAUSED", Color::Yellow),
+        (flags.cancelling, "CANCELLING", Color::Yellow),
+        (flags.error, "ERROR", Color::Red),
+        (flags.closed_or_error, "CLOSED", Color::Red),
+    ]
+    .iter()
+    .filter(|&&(set, _, _)| set)
+    .map(|&(_, name, color)| (name, color))
+    .collect()
+}
+
+/// How close a reading is to its target, which picks its color
+fn temperature_heat(temp: Option<f64>, target: Option<f64>) -> Heat {
+    match (temp, target) {
+        (Some(temp), Some(target)) if target > 0.0 => {
+            let delta = temp - target;
+            if delta > AT_TARGET_DEGREES {
+                Heat::AboveTarget
+            } else if delta >= -AT_TARGET_DEGREES {
+                Heat::AtTarget
+            } else if delta >= -NEAR_TARGET_DEGREES {
+                Heat::NearTarget
+            } else {
+                Heat::Heating
+            }
+        }
+        _ => Heat::Off,
+    }
+}
+
+fn percent_or_dashes(percent: Option<f64>) -> String {
+    percent
+        .map(|p| format!("{:.0}%", p))
+        .unwrap_or("--".to_string())
+}
+
+/// Keeps a percentage in the 0-100 range the gauge accepts, treating NaN as 0
+fn clamp_percent(percent: f64) -> f64 {
+    if percent.is_nan() {
+        0.0
+    } else {
+        percent.max(0.0).min(100.0)
+    }
+}
+
+fn seconds_to_time(seconds: f64) -> (u64, u64, f64) {
+    let hours = (seconds / (60.0 * 60.0)) as u64;
+    let seconds = seconds % (60.0 * 60.0);
+    let minutes = (seconds / 60.0) as u64;
+    let seconds = seconds % 60.0;
+
+    (hours, minutes, seconds)
+}
+
+/// Collects the printable files out of the folder tree
+fn flatten_files(files: Vec<FileInfo>, flattened: &mut Vec<FileInfo>) {
+    for mut file in files {
+        if file.file_type == FileType::Folder {
+            flatten_files(file.children.take().unwrap_or_default(), flattened);
+        } else {
+            flattened.push(file);
+        }
+    }
+}
+
+/// Draws a labeled temperature reading like `200.0/200°C (+5°)`, with the name highlighted
+/// while focused
+fn draw_temperature<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    name: &str,
+    field: Option<TemperatureField>,
+    temp: Option<f64>,
+    target: Option<f64>,
+    offset: Option<f64>,
+    state: &UiState,
+    style: Style,
+) {
+    let units = state.units;
+    let value_style = if state.stale {
+        state.theme.fg(STALE_COLOR)
+    } else {
+        state.theme.temperature(temperature_heat(temp, target))
+    };
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(0)
+        .constraints(
+            [
+                Constraint::Length(1),
+                Constraint::Length(1),
+                Constraint::Length(1),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    let name_style = if field.is_some() && state.focused_temperature == field {
+        state.theme.modifier(style, Modifier::REVERSED)
+    } else {
+        style
+    };
+    Paragraph::new([Text::Styled(name.into(), name_style)].iter())
+        .style(style)
+        .alignment(Alignment::Center)
+        .render(f, chunks[0]);
+
+    Paragraph::new(
+        [Text::Styled(
+            format!(
+                "{}/{}{}{}",
+                temp.map(|t| state.temperature_format.reading(units.from_celsius(t)))
+                    .unwrap_or("--".to_string()),
+                target
+                    .map(|t| format!("{:.0}", units.from_celsius(t)))
+                    .unwrap_or("--".to_string()),
+                units.symbol(),
+                offset
+                    .filter(|&o| o != 0.0)
+                    .map(|o| format!(" ({:+.0}°)", units.difference_from_celsius(o)))
+                    .unwrap_or_default(),
+            )
+            .into(),
+            value_style,
+        )]
+        .iter(),
+    )
+    .style(style)
+    .alignment(Alignment::Center)
+    .render(f, chunks[1]);
+
+    // A heater that is off has nothing to heat up to
+    if let (Some(temp), Some(target)) = (temp, target) {
+        if area.height >= 3 && target > 0.0 {
+            let percent = (temp / target * 100.0).max(0.0).min(100.0);
+            Gauge::default()
+                .style(value_style)
+                .percent(percent as u16)
+                .render(f, chunks[2]);
+        }
+    }
+}
+
+/// Turns one value of the temperature history into chart points, with x being seconds before now
+fn history_points<F>(
+    history: &VecDeque<TemperatureSample>,
+    now: Instant,
+    units: TemperatureUnit,
+    value: F,
+) -> Vec<(f64, f64)>
+where
+    F: Fn(&TemperatureSample) -> Option<f64>,
+{
+    history
+        .iter()
+        .filter_map(|sample| {
+            value(sample).map(|v| {
+                (
+                    -seconds(now.duration_since(sample.time)),
+                    units.from_celsius(v),
+                )
+            })
+        })
+        .collect()
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_millis()) / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tui::backend::TestBackend;
+
+    fn test_ui() -> Ui<TestBackend> {
+        let terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let (commands, _) = mpsc::channel(10);
+        let options = UiOptions {
+            jog_step: 10.0,
+            babystep: 0.05,
+            units: TemperatureUnit::Celsius,
+            poll_interval: Duration::from_secs(1),
+            idle_poll_interval: Duration::from_secs(5),
+            notify_command: None,
+            hooks: Hooks::default(),
+            spool_length: None,
+            show_inactive_tools: false,
+            profiles: Vec::new(),
+            profile: None,
+            presets: Vec::new(),
+            theme: Theme::default_theme(),
+            view: View::Overview,
+            webcam: false,
+            sections: Sections {
+                bed: true,
+                filament: true,
+                estimated: true,
+                tuning: true,
+            },
+            idle_dim: None,
+            compact: false,
+            metrics: None,
+            temperature_format: TemperatureFormat {
+                precision: 1,
+                decimal_separator: '.',
+            },
+        };
+        Ui::new(terminal, commands, options)
+    }
+
+    fn job(json: &str) -> UiEvent {
+        UiEvent::JobUpdate(serde_json::from_str(json).unwrap())
+    }
+
+    fn printer(json: &str) -> UiEvent {
+        UiEvent::StateUpdate(serde_json::from_str(json).unwrap())
+    }
+
+    /// Handles the events and draws on an 80x24 screen, giving back each row without the
+    /// trailing spaces
+    fn render(events: Vec<UiEvent>) -> Vec<String> {
+        let mut ui = test_ui();
+        for event in events {
+            ui.handle(event);
+        }
+        ui.draw();
+        let buffer = ui.terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area().width as usize)
+            .map(|row| {
+                let row: String = row.iter().map(|cell| cell.symbol.as_str()).collect();
+                row.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    /// Checks the rows that don't change with the clock
+    fn assert_rows(screen: &[String], rows: &[(usize, &str)]) {
+        for &(row, expected) in rows {
+            assert_eq!(
+                screen[row],
+                expected,
+                "row {} of\n{}",
+                row,
+                screen.join("\n")
+            );
+        }
+    }
+
+    const IDLE_JOB: &str = r#"{"job": {"file": {"name": null}}, "progress": {}}"#;
+
+    const OPERATIONAL: &str = r#"{
+        "temperature": {
+            "tool0": {"actual": 24.1, "target": 0.0},
+            "bed": {"actual": 23.5, "target": 0.0}
+        },
+        "state": {"text": "Operational", "flags": {"operational": true, "ready": true}}
+    }"#;
+
+    #[test]
+    fn idle_layout() {
+        let screen = render(vec![
+            UiEvent::PushActive(true),
+            job(IDLE_JOB),
+            printer(OPERATIONAL),
+        ]);
+        assert_rows(
+            &screen,
+            &[
+                (1, " OPERATIONAL   READY               Operational"),
+                (2, " 1 Overview │ 2 Files │ 3 Temps │ 4 Console"),
+                (4, "                                     No File"),
+                (5, "                                  Filament: --"),
+                (6, "                           Fan: -- Speed: -- Flow: --"),
+                (
+                    9,
+                    "                 Hotend                                    Bed",
+                ),
+                (
+                    10,
+                    "                24.1/0°C                                23.5/0°C",
+                ),
+                (
+                    20,
+                    "         --:--:--                  --:--:--            --:--:-- Done at --:--",
+                ),
+                (22, "                                     0.00%"),
+                (23, ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn printing_layout() {
+        let screen = render(vec![
+            UiEvent::PushActive(true),
+            job(r#"{
+                "job": {
+                    "file": {"name": "benchy.gcode", "origin": "local"},
+                    "estimatedPrintTime": 5400.0
+                },
+                "progress": {"completion": 42.5, "printTime": 2300, "printTimeLeft": 3100}
+            }"#),
+            printer(
+                r#"{
+                "temperature": {
+                    "tool0": {"actual": 214.6, "target": 215.0},
+                    "bed": {"actual": 60.2, "target": 60.0}
+                },
+                "state": {"text": "Printing", "flags": {"operational": true, "printing": true}}
+            }"#,
+            ),
+        ]);
+        assert_rows(
+            &screen,
+            &[
+                (1, " OPERATIONAL   PRINTING             Printing"),
+                (4, "                                  benchy.gcode"),
+                (
+                    10,
+                    "               214.6/215°C                              60.2/60°C",
+                ),
+                (
+                    11,
+                    "                  99%                                     100%",
+                ),
+                (
+                    19,
+                    "        Print Time              Estimated Time             Remaining Time",
+                ),
+                (22, "                                     42.50%"),
+            ],
+        );
+        // The rest of the row has the time the print will be done at
+        assert!(screen[20].starts_with("          0:38:20             1:30:00 (0% behind)"));
+    }
+
+    #[test]
+    fn tuning_needs_push() {
+        let screen = render(vec![job(IDLE_JOB), printer(OPERATIONAL)]);
+        assert_eq!(screen[5], "                                  Filament: --");
+        assert!(!screen.iter().any(|row| row.contains("Fan:")));
+    }
+
+    #[test]
+    fn chamber_column() {
+        let with_chamber = render(vec![
+            UiEvent::PushActive(true),
+            job(IDLE_JOB),
+            printer(include_str!("../tests/fixtures/printer-chamber.json")),
+        ]);
+        assert!(with_chamber[9].contains("Chamber"));
+        assert!(with_chamber[10].contains("41.3/45°C"));
+
+        let without = render(vec![job(IDLE_JOB), printer(OPERATIONAL)]);
+        assert!(!without.iter().any(|row| row.contains("Chamber")));
+    }
+
+    #[test]
+    fn error_layout() {
+        let screen = render(vec![
+            job(IDLE_JOB),
+            printer(OPERATIONAL),
+            UiEvent::Error(UiError::Octoprint(OctoprintError::Timeout(
+                Duration::from_secs(10),
+            ))),
+        ]);
+        assert_rows(
+            &screen,
+            &[
+                (1, " OPERATIONAL   READY               Operational"),
+                (4, "                                     No File"),
+            ],
+        );
+        // After the time the error happened
+        assert!(screen[23].ends_with(" No response after 10 seconds"));
+    }
+
+    #[test]
+    fn only_poll_errors_mark_stale() {
+        let mut ui = test_ui();
+        let state = &mut ui.state;
+        state.apply(&printer(OPERATIONAL));
+        state.apply(&UiEvent::Error(UiError::Octoprint(
+            OctoprintError::Forbidden {
+                path: "/api/job".to_string(),
+                reason: None,
+            },
+        )));
+        assert!(!state.stale);
+        state.apply(&UiEvent::Error(UiError::Poll(OctoprintError::Timeout(
+            Duration::from_secs(10),
+        ))));
+        assert!(state.stale);
+        assert_eq!(state.stats.consecutive_failures, 1);
+    }
+
+    /// A job at the given completion, 1000 seconds in, sliced for 2000 seconds
+    fn paced_job(completion: f64) -> UiEvent {
+        job(&format!(
+            r#"{{
+                "job": {{"file": {{"name": "a.gcode"}}, "estimatedPrintTime": 2000.0, "lastPrintTime": 4000.0}},
+                "progress": {{"completion": {}, "printTime": 1000}}
+            }}"#,
+            completion
+        ))
+    }
+
+    #[test]
+    fn pace_waits_for_five_percent() {
+        let mut ui = test_ui();
+        ui.state.apply(&paced_job(4.9));
+        assert_eq!(ui.state.pace(), None);
+        ui.state.apply(&paced_job(5.0));
+        assert!(ui.state.pace().is_some());
+    }
+
+    #[test]
+    fn pace_is_against_the_slicer_estimate() {
+        let mut ui = test_ui();
+        // 60% of the 2000 seconds estimate is 1200 seconds, so 1000 is ahead
+        ui.state.apply(&paced_job(60.0));
+        assert!((ui.state.pace().unwrap() - 20.0).abs() < 1e-9);
+        // 40% is 800 seconds, so 1000 is behind
+        ui.state.apply(&paced_job(40.0));
+        assert!((ui.state.pace().unwrap() + 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn updates_only_clear_poll_errors() {
+        let mut ui = test_ui();
+        ui.handle(UiEvent::Error(UiError::Octoprint(
+            OctoprintError::Forbidden {
+                path: "/api/job".to_string(),
+                reason: None,
+            },
+        )));
+        ui.handle(printer(OPERATIONAL));
+        assert!(ui.state.error.is_some());
+
+        ui.handle(UiEvent::Error(UiError::Poll(OctoprintError::Timeout(
+            Duration::from_secs(10),
+        ))));
+        assert!(ui.state.poll_error.is_some());
+        ui.handle(printer(OPERATIONAL));
+        assert!(ui.state.poll_error.is_none());
+        assert!(ui.state.error.is_some());
+
+        // An unbound key
+        ui.handle(UiEvent::Key(Key::Char('`')));
+        assert!(ui.state.error.is_none());
+    }
+
+    const PRINTING: &str = r#"{
+        "state": {"text": "Printing", "flags": {"operational": true, "printing": true}}
+    }"#;
+
+    fn file_job(completion: f64) -> UiEvent {
+        job(&format!(
+            r#"{{
+                "job": {{"file": {{"name": "a.gcode", "path": "a.gcode", "origin": "local"}}}},
+                "progress": {{"completion": {}}}
+            }}"#,
+            completion
+        ))
+    }
+
+    #[test]
+    fn apply_loads_file_detail_once() {
+        let mut ui = test_ui();
+        let effects = ui.state.apply(&file_job(42.5));
+        match effects.as_slice() {
+            [Effect::Command(Command::LoadFileDetail { origin, path })] => {
+                assert_eq!(*origin, Origin::Local);
+                assert_eq!(path, "a.gcode");
+            }
+            _ => panic!("expected one file detail command"),
+        }
+        assert_eq!(ui.state.progress, 42.5);
+        assert_eq!(ui.state.filename, Some("a.gcode".to_string()));
+
+        assert!(ui.state.apply(&file_job(43.0)).is_empty());
+    }
+
+    #[test]
+    fn apply_notifies_only_for_finished_prints() {
+        let mut ui = test_ui();
+        ui.state.apply(&printer(PRINTING));
+        ui.state.apply(&file_job(100.0));
+        match ui.state.apply(&printer(OPERATIONAL)).as_slice() {
+            [Effect::NotifyComplete] => {}
+            _ => panic!("expected a completion notification"),
+        }
+
+        // Cancelled part way
+        let mut ui = test_ui();
+        ui.state.apply(&printer(PRINTING));
+        ui.state.apply(&file_job(50.0));
+        assert!(ui.state.apply(&printer(OPERATIONAL)).is_empty());
+    }
+
+    #[test]
+    fn help_scrolls_to_the_last_binding() {
+        let last = "Switch to another printer profile";
+        let opened = render(vec![UiEvent::Key(Key::Char('?'))]);
+        assert!(opened.iter().any(|row| row.contains("Show this help")));
+        assert!(
+            opened[0].contains("Help (1-20 of 36, Up/Down to scroll)"),
+            "{}",
+            opened.join("\n")
+        );
+        assert!(!opened.iter().any(|row| row.contains(last)));
+
+        let mut events = vec![UiEvent::Key(Key::Char('?'))];
+        events.extend((0..3).map(|_| UiEvent::Key(Key::PageDown)));
+        let scrolled = render(events);
+        assert!(scrolled[22].contains(last), "{}", scrolled.join("\n"));
+        assert!(scrolled[0].contains("Help (17-36 of 36"));
+    }
 }
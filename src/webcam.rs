@@ -0,0 +1,99 @@
+use tui::buffer::Buffer;
+use tui::layout::Rect;
+use tui::style::Color;
+use tui::widgets::Block;
+use tui::widgets::Widget;
+
+/// The largest snapshot kept, in pixels, which is already more than most terminals can show
+#[cfg(feature = "webcam")]
+const SNAPSHOT_WIDTH: u32 = 160;
+#[cfg(feature = "webcam")]
+const SNAPSHOT_HEIGHT: u32 = 120;
+
+/// A downscaled webcam snapshot
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    width: usize,
+    height: usize,
+    /// Rows of RGB pixels from the top left
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Snapshot {
+    /// Decodes a JPEG snapshot and shrinks it to fit the largest snapshot size
+    #[cfg(feature = "webcam")]
+    pub fn decode(jpeg: &[u8]) -> Result<Snapshot, image::ImageError> {
+        let image = image::load_from_memory(jpeg)?
+            .thumbnail(SNAPSHOT_WIDTH, SNAPSHOT_HEIGHT)
+            .to_rgb();
+        let (width, height) = image.dimensions();
+        Ok(Snapshot {
+            width: width as usize,
+            height: height as usize,
+            pixels: image.pixels().map(|pixel| pixel.data).collect(),
+        })
+    }
+
+    fn color(&self, x: usize, y: usize) -> Color {
+        let [r, g, b] = self.pixels[y * self.width + x];
+        Color::Rgb(r, g, b)
+    }
+}
+
+/// Draws a snapshot as upper half blocks, so each cell shows one pixel in its text color on top
+/// of another in its background color. The snapshot is scaled to fit and centered.
+pub struct SnapshotView<'a> {
+    snapshot: &'a Snapshot,
+    block: Option<Block<'a>>,
+}
+
+impl<'a> SnapshotView<'a> {
+    pub fn new(snapshot: &'a Snapshot) -> SnapshotView<'a> {
+        SnapshotView {
+            snapshot,
+            block: None,
+        }
+    }
+
+    pub fn block(mut self, block: Block<'a>) -> SnapshotView<'a> {
+        self.block = Some(block);
+        self
+    }
+}
+
+impl<'a> Widget for SnapshotView<'a> {
+    fn draw(&mut self, area: Rect, buf: &mut Buffer) {
+        let area = match self.block {
+            Some(ref mut block) => {
+                block.draw(area, buf);
+                block.inner(area)
+            }
+            None => area,
+        };
+
+        let snapshot = self.snapshot;
+        if area.width == 0 || area.height == 0 || snapshot.width == 0 || snapshot.height == 0 {
+            return;
+        }
+
+        // Each cell is two pixels tall, which keeps the pixels about square
+        let scale = (f64::from(area.width) / snapshot.width as f64)
+            .min(f64::from(area.height) * 2.0 / snapshot.height as f64);
+        let columns = ((snapshot.width as f64 * scale) as u16).max(1);
+        let rows = ((snapshot.height as f64 * scale / 2.0) as u16).max(1);
+        let left = area.left() + (area.width - columns) / 2;
+        let top = area.top() + (area.height - rows) / 2;
+
+        for row in 0..rows {
+            let upper = (row as usize * 2) * snapshot.height / (rows as usize * 2);
+            let lower = (row as usize * 2 + 1) * snapshot.height / (rows as usize * 2);
+            for column in 0..columns {
+                let x = column as usize * snapshot.width / columns as usize;
+                buf.get_mut(left + column, top + row)
+                    .set_symbol("▀")
+                    .set_fg(snapshot.color(x, upper))
+                    .set_bg(snapshot.color(x, lower));
+            }
+        }
+    }
+}